@@ -79,12 +79,11 @@ mod tests {
         let vert_line = (b, d, e);
         let diag_line = (a, d, f);
 
-        let lines = vec![horz_line, vert_line, diag_line];
+        let lines = [horz_line, vert_line, diag_line];
 
         for (i, line) in lines.iter().enumerate() {
-            assert_eq!(
+            assert!(
                 points_collinear(line.0, line.1, line.2),
-                true,
                 "Points at index {} not on a line",
                 i
             );
@@ -104,12 +103,11 @@ mod tests {
         let tri2 = (b, f, e);
         let tri3 = (a, d, e);
 
-        let triangles = vec![tri1, tri2, tri3];
+        let triangles = [tri1, tri2, tri3];
 
         for (i, triangle) in triangles.iter().enumerate() {
-            assert_eq!(
-                points_collinear(triangle.0, triangle.1, triangle.2),
-                false,
+            assert!(
+                !points_collinear(triangle.0, triangle.1, triangle.2),
                 "Points at index {} on a line",
                 i
             );
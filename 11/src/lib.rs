@@ -1,10 +1,21 @@
-use intcode::{IntCode, Op};
+use intcode::{Device, IntCode, Op, VmError};
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
 type Coord = (i32, i32);
 type Panels = HashMap<Coord, Color>;
-pub type PaintError = ();
+
+#[derive(Debug)]
+pub enum PaintError {
+    Vm(VmError),
+    BadOutput(Op),
+}
+
+impl From<VmError> for PaintError {
+    fn from(err: VmError) -> Self {
+        PaintError::Vm(err)
+    }
+}
 
 // Colors
 #[derive(Clone, Copy)]
@@ -14,13 +25,13 @@ pub enum Color {
 }
 
 impl TryFrom<Op> for Color {
-    type Error = ();
+    type Error = PaintError;
 
     fn try_from(item: Op) -> Result<Self, Self::Error> {
         match item {
             0 => Ok(Color::Black),
             1 => Ok(Color::White),
-            _ => Err(()),
+            _ => Err(PaintError::BadOutput(item)),
         }
     }
 }
@@ -31,13 +42,13 @@ enum Turn {
 }
 
 impl TryFrom<Op> for Turn {
-    type Error = ();
+    type Error = PaintError;
 
     fn try_from(op: Op) -> Result<Self, Self::Error> {
         match op {
             0 => Ok(Self::CounterClockwise),
             1 => Ok(Self::Clockwise),
-            _ => Err(()),
+            _ => Err(PaintError::BadOutput(op)),
         }
     }
 }
@@ -49,57 +60,71 @@ enum Dir {
     Left,
 }
 
-pub fn paint(program: &str, initial_color: Color) -> Result<Panels, PaintError> {
-    let mut panels: HashMap<Coord, Color> = HashMap::new();
-    let mut computer = IntCode::new(program);
-    let mut pos = (0, 0);
-    let mut dir = Dir::Up;
-
-    loop {
-        let panel_color: Color;
-        if let Some(color) = panels.get(&pos) {
-            panel_color = *color;
-        } else {
-            panel_color = initial_color;
-        }
+/// The hull painting robot. Reports the color under it when asked, and
+/// takes output as (color to paint, turn) pairs.
+struct Robot {
+    panels: Panels,
+    initial_color: Color,
+    pos: Coord,
+    dir: Dir,
+}
+
+impl Device for Robot {
+    type Error = PaintError;
+
+    fn arity(&self) -> usize {
+        2
+    }
 
-        computer.input(panel_color as Op);
+    fn output(&mut self, frame: &[Op]) -> Result<(), PaintError> {
+        let new_color = Color::try_from(frame[0])?;
+        let turn = Turn::try_from(frame[1])?;
+        self.dir = match turn {
+            Turn::CounterClockwise => match self.dir {
+                Dir::Up => Dir::Left,
+                Dir::Right => Dir::Up,
+                Dir::Down => Dir::Right,
+                Dir::Left => Dir::Down,
+            },
+            Turn::Clockwise => match self.dir {
+                Dir::Up => Dir::Right,
+                Dir::Right => Dir::Down,
+                Dir::Down => Dir::Left,
+                Dir::Left => Dir::Up,
+            },
+        };
 
-        let new_color: Color = match computer.compute_output() {
-            Some(output) => Color::try_from(output)?,
-            None => return Ok(panels),
+        let pos = self.pos;
+        self.panels.insert(pos, new_color);
+        self.pos = match self.dir {
+            Dir::Up => (pos.0, pos.1 + 1),
+            Dir::Down => (pos.0, pos.1 - 1),
+            Dir::Right => (pos.0 + 1, pos.1),
+            Dir::Left => (pos.0 - 1, pos.1),
         };
 
-        if let Some(dir_change_op) = computer.compute_output() {
-            let turn = Turn::try_from(dir_change_op)?;
-            dir = match turn {
-                Turn::CounterClockwise => match dir {
-                    Dir::Up => Dir::Left,
-                    Dir::Right => Dir::Up,
-                    Dir::Down => Dir::Right,
-                    Dir::Left => Dir::Down,
-                },
-                Turn::Clockwise => match dir {
-                    Dir::Up => Dir::Right,
-                    Dir::Right => Dir::Down,
-                    Dir::Down => Dir::Left,
-                    Dir::Left => Dir::Up,
-                },
-            };
+        Ok(())
+    }
 
-            panels.insert(pos, new_color);
-            pos = match dir {
-                Dir::Up => (pos.0, pos.1 + 1),
-                Dir::Down => (pos.0, pos.1 - 1),
-                Dir::Right => (pos.0 + 1, pos.1),
-                Dir::Left => (pos.0 - 1, pos.1),
-            };
-        } else {
-            return Ok(panels);
-        }
+    fn input(&mut self) -> Result<Option<Op>, PaintError> {
+        let color = self.panels.get(&self.pos).unwrap_or(&self.initial_color);
+        Ok(Some(*color as Op))
     }
 }
 
+pub fn paint(program: &str, initial_color: Color) -> Result<Panels, PaintError> {
+    let mut computer = IntCode::new(program)?;
+    let mut robot = Robot {
+        panels: HashMap::new(),
+        initial_color,
+        pos: (0, 0),
+        dir: Dir::Up,
+    };
+
+    computer.run_device(&mut robot)?;
+    Ok(robot.panels)
+}
+
 pub fn print_panels(panels: &Panels) -> String {
     let min_x = panels
         .keys()
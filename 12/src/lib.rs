@@ -74,7 +74,7 @@ pub fn advance_state(time: Int, moons: &mut [Moon]) {
 }
 
 pub fn total_energy_universe(moons: &[Moon]) -> Int {
-    moons.iter().map(|moon| total_energy_moon(moon)).sum()
+    moons.iter().map(total_energy_moon).sum()
 }
 
 pub fn find_duplicate_state_dim(moons: &mut Moons, dim: usize) -> (Int, Int) {
@@ -1,5 +1,4 @@
-mod lib;
-use lib::*;
+use fourteen::*;
 
 fn main() -> Result<()> {
     let formulas = parse_formulas(FOURTEEN)?;
@@ -14,7 +14,7 @@ struct Chem<'a> {
 impl<'a> TryFrom<&'a str> for Chem<'a> {
     type Error = ();
 
-    fn try_from(input: &str) -> std::result::Result<Chem, Self::Error> {
+    fn try_from(input: &str) -> std::result::Result<Chem<'_>, Self::Error> {
         let components: Vec<&str> = input.trim().split(' ').collect();
         let amount: ChemAmount = match components[0].parse() {
             Ok(x) => x,
@@ -32,7 +32,7 @@ pub struct Equation<'a> {
     output: Chem<'a>,
 }
 
-pub fn parse_formulas<'a>(input: &'a str) -> Result<Equations> {
+pub fn parse_formulas<'a>(input: &'a str) -> Result<Equations<'a>> {
     let mut formula = Equations::new();
     for line in input.lines() {
         let eq: Vec<&str> = line.trim().split("=>").collect();
@@ -80,7 +80,7 @@ pub fn ore_for_fuel(equations: &Equations, fuel: ChemAmount) -> ChemAmount {
 
             // How many instances of chem eq need to run?
             // (x + y - 1) / y  is x/y rounded up instead of down
-            let multiplier = (needed + equation.output.amount - 1) / equation.output.amount;
+            let multiplier = needed.div_ceil(equation.output.amount);
 
             // Add output to slush fund
             *extra.get_mut(equation.output.name).unwrap() += equation.output.amount * multiplier;
@@ -1,7 +1,8 @@
-mod lib;
-use lib::*;
+use fifteen::*;
 
-fn main() {
-    let dist = biggest_dist_to_oxygen();
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let dist = biggest_dist_to_oxygen()?;
     println!("\n{}", dist);
+
+    Ok(())
 }
@@ -1,74 +1,73 @@
+use intcode::{Op, Patch, Program, Solutions, VmError};
 use std::error::Error;
-use std::fs::read_to_string;
+use std::ops::Range;
 
-const ADD: u32 = 1;
-const MULTIPLY: u32 = 2;
-const HCF: u32 = 99;
+const NOUN: usize = 1;
+const VERB: usize = 2;
+const NOUN_VERB_RANGE: Range<Op> = 0..100;
+const TARGET: Op = 19_690_720;
 
-fn compute(ops: &mut Vec<u32>) {
-    // ip - instruction pointer
-    // a - first register
-    // b - second register
-    // r - result register
-    let mut ip = 0; // Instruction pointer
-
-    let mut op = ops[ip];
-
-    while op != HCF {
-        let ap = ops[ip + 1] as usize;
-        let bp = ops[ip + 2] as usize;
-        let rp = ops[ip + 3] as usize;
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
-        let x = match op {
-            ADD => ops[ap] + ops[bp],
-            MULTIPLY => ops[ap] * ops[bp],
-            _ => panic!("Unrecognized instruction"),
-        };
+/// Address 0 after running with `noun` and `verb` patched in
+fn compute(program: &Program, noun: Op, verb: Op) -> std::result::Result<Op, VmError> {
+    let patch = Patch::new("noun and verb").set(NOUN, noun).set(VERB, verb);
+    let mut computer = program.clone().patch(patch).spawn();
+    computer.run()?;
 
-        ops[rp] = x;
-        ip += 4;
-        op = ops[ip];
-    }
+    Ok(computer.memory()[0])
 }
 
-type Result<T> = std::result::Result<T, Box<dyn Error>>;
-
-fn init() -> Result<Vec<u32>> {
-    let input = read_to_string("input.txt")?;
-    let mut ops: Vec<u32> = Vec::new();
-    for op_str in input.split(',') {
-        let op: u32 = op_str.parse()?;
-        ops.push(op);
-    }
-
-    Ok(ops)
+fn find_noun_verb(program: &Program, target: Op) -> Solutions {
+    program.solve(&[(NOUN, NOUN_VERB_RANGE), (VERB, NOUN_VERB_RANGE)], target)
 }
 
 fn main() -> Result<()> {
-    let target = 19_690_720;
-
-    // Dumb brute force
-    for a in 1..100 {
-        for b in 1..100 {
-            let mut ops = init()?;
-            ops[1] = a;
-            ops[2] = b;
+    let program = Program::load("input.txt")?;
+    println!("1202 program alarm: {}", compute(&program, 12, 2)?);
 
-            compute(&mut ops);
-            if ops[0] == target {
-                println!("perfect a: {}, b: {}", a, b);
-            }
-        }
+    let solutions = find_noun_verb(&program, TARGET);
+    if let Some(formula) = &solutions.formula {
+        println!("[0] = {}", formula);
+    }
+    for values in solutions.values.iter() {
+        println!(
+            "noun {}, verb {}: {}",
+            values[0],
+            values[1],
+            100 * values[0] + values[1]
+        );
     }
-
-    println!("end");
-    //println!("{}", ops[0]);
 
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    fn program() -> Program {
+        Program::new(include_str!("../input.txt").trim()).unwrap()
+    }
+
+    #[test]
+    fn two_1() {
+        assert_eq!(compute(&program(), 12, 2), Ok(5_482_655));
+    }
+
     #[test]
-    fn exploration() {}
+    fn two_2() {
+        let solutions = find_noun_verb(&program(), TARGET);
+        assert!(solutions.formula.is_some());
+        assert_eq!(solutions.values, vec![vec![49, 67]]);
+        assert_eq!(compute(&program(), 49, 67), Ok(TARGET));
+    }
+
+    #[test]
+    fn sample() {
+        let program = Program::new(include_str!("../sample1.txt").trim()).unwrap();
+        let mut computer = program.spawn();
+        computer.run().unwrap();
+        assert_eq!(computer.memory()[0], 2);
+    }
 }
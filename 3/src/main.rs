@@ -31,7 +31,7 @@ fn find_distance(input: String) -> Result<u32, Box<dyn Error>> {
     let wire2 = wire_segments(segments.next().unwrap());
 
     let mut intersections = find_intersections(wire1, wire2);
-    intersections.sort_by(|a, b| a.travel_steps.cmp(&b.travel_steps));
+    intersections.sort_by_key(|a| a.travel_steps);
     let first = intersections.first().unwrap();
 
     //println!("first {:?}", intersections.first().unwrap());
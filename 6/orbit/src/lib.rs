@@ -8,7 +8,7 @@ const COM: &str = "COM";
 const SAN: &str = "SAN";
 const YOU: &str = "YOU";
 
-pub fn parse_input(input: &str) -> OrbitMap {
+pub fn parse_input(input: &str) -> OrbitMap<'_> {
     input
         .lines()
         .map(|line| -> (&str, &str) {
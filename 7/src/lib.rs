@@ -1,12 +1,19 @@
-use intcode::{IntCode, Op};
+use intcode::{block_on, channel, join_all, Engine, IntCode, Op, ThreadedNetwork};
+use std::error::Error;
 use std::ops::Range;
 
+pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
 const AMP_COUNT: usize = 5;
+const AMP_NAMES: [&str; AMP_COUNT] = ["A", "B", "C", "D", "E"];
+// Only one amp in a loop runs at a time, so this many loops keeps a few
+// cores busy without starting a thread for every amp of every loop
+const LOOPS_AT_ONCE: usize = 8;
 const PHASE_RANGE: Range<Op> = 0..5;
 const PHASE_RANGE_REPEAT: Range<Op> = 5..10;
 pub type PhaseSettings = [Op; AMP_COUNT];
 
-pub fn find_max_phase_setting(program: &str) -> (Op, PhaseSettings) {
+pub fn find_max_phase_setting(program: &str) -> Result<(Op, PhaseSettings)> {
     let mut max_thruster = 0;
     let mut phase_settings: PhaseSettings = [0; AMP_COUNT];
 
@@ -17,7 +24,7 @@ pub fn find_max_phase_setting(program: &str) -> (Op, PhaseSettings) {
                     for e in PHASE_RANGE {
                         let test_settings = [a, b, c, d, e];
                         if valid_phase_setting(&test_settings) {
-                            let test_signal = thruster_signal(program, &test_settings);
+                            let test_signal = thruster_signal(program, &test_settings)?;
                             if max_thruster < test_signal {
                                 max_thruster = test_signal;
                                 phase_settings = test_settings;
@@ -29,22 +36,28 @@ pub fn find_max_phase_setting(program: &str) -> (Op, PhaseSettings) {
         }
     }
 
-    (max_thruster, phase_settings)
+    Ok((max_thruster, phase_settings))
 }
 
-fn thruster_signal(program: &str, phase_settings: &PhaseSettings) -> Op {
+fn thruster_signal(program: &str, phase_settings: &PhaseSettings) -> Result<Op> {
     let mut input_signal = 0;
     for &setting in phase_settings {
         let program_input = [setting, input_signal];
-        let mut computer = IntCode::new(program);
+        let mut computer = amplifier(program)?;
         for &input in program_input.iter() {
             computer.input(input);
         }
 
-        input_signal = computer.compute_output().expect("no output");
+        input_signal = computer.compute_output()?.expect("no output");
     }
 
-    input_signal
+    Ok(input_signal)
+}
+
+fn amplifier(program: &str) -> Result<IntCode> {
+    let mut computer = IntCode::new(program)?;
+    computer.set_engine(Engine::Cached);
+    Ok(computer)
 }
 
 fn valid_phase_setting(phase: &PhaseSettings) -> bool {
@@ -65,30 +78,62 @@ fn valid_feedback_phase_setting(phase: &PhaseSettings) -> bool {
     contains_all
 }
 
-fn feedback(program: &str, phase: &PhaseSettings) -> Op {
-    let mut output = Some(0);
-    let mut amps: Vec<IntCode> = (0..AMP_COUNT).map(|_| IntCode::new(program)).collect();
+/// Runs the feedback loop with each amp as an async task, all joined on
+/// this thread
+pub fn feedback(program: &str, phase: &PhaseSettings) -> Result<Op> {
+    let mut senders = Vec::with_capacity(AMP_COUNT);
+    let mut receivers = Vec::with_capacity(AMP_COUNT);
+    for &setting in phase.iter() {
+        let (sender, receiver) = channel();
+        sender.send(setting);
+        senders.push(sender);
+        receivers.push(receiver);
+    }
+    senders[0].send(0);
 
-    // Initialize phases settings
-    for i in 0..AMP_COUNT {
-        amps[i].input(phase[i]);
+    // Each amp writes to the next one's input, the last to the first's
+    senders.rotate_left(1);
+    let mut amps = Vec::with_capacity(AMP_COUNT);
+    for (input, output) in receivers.into_iter().zip(senders) {
+        amps.push(amplifier(program)?.into_async(input).pipe(output));
     }
 
-    let mut amp_index = 0;
-    while let Some(input) = output {
-        let amp = &mut amps[amp_index];
-        amp.input(input);
-        output = amp.compute_output();
-        amp_index = (amp_index + 1) % AMP_COUNT;
+    let mut finished = Vec::with_capacity(AMP_COUNT);
+    for amp in block_on(join_all(amps)) {
+        finished.push(amp?);
     }
 
-    amps[AMP_COUNT - 1].last_output()
+    // The last amp's final output loops back around to the first
+    let (_, mut first_input) = finished.swap_remove(0).into_inner();
+    Ok(first_input.try_recv().ok_or("no output")?)
 }
 
-pub fn find_max_feedback_phase_setting(program: &str) -> (Op, PhaseSettings) {
+/// Starts a feedback loop with each amp on its own thread
+fn start_feedback(program: &str, phase: &PhaseSettings) -> Result<ThreadedNetwork> {
+    let mut amps = Vec::with_capacity(AMP_COUNT);
+    for &name in AMP_NAMES.iter() {
+        amps.push((name, amplifier(program)?));
+    }
+
+    let mut network = ThreadedNetwork::ring(amps);
+    for (name, &setting) in AMP_NAMES.iter().zip(phase.iter()) {
+        network.send(name, setting);
+    }
+
+    network.send(AMP_NAMES[0], 0);
+    Ok(network)
+}
+
+fn finish_feedback(network: ThreadedNetwork) -> Result<Op> {
+    let amps = network.join()?;
+    Ok(amps[0].input.try_recv()?)
+}
+
+pub fn find_max_feedback_phase_setting(program: &str) -> Result<(Op, PhaseSettings)> {
     let mut max_thruster = 0;
     let mut phase_settings: PhaseSettings = [0; AMP_COUNT];
 
+    let mut candidates = Vec::new();
     for a in PHASE_RANGE_REPEAT {
         for b in PHASE_RANGE_REPEAT {
             for c in PHASE_RANGE_REPEAT {
@@ -96,11 +141,7 @@ pub fn find_max_feedback_phase_setting(program: &str) -> (Op, PhaseSettings) {
                     for e in PHASE_RANGE_REPEAT {
                         let test_settings = [a, b, c, d, e];
                         if valid_feedback_phase_setting(&test_settings) {
-                            let test_signal = feedback(program, &test_settings);
-                            if max_thruster < test_signal {
-                                max_thruster = test_signal;
-                                phase_settings = test_settings;
-                            }
+                            candidates.push(test_settings);
                         }
                     }
                 }
@@ -108,7 +149,23 @@ pub fn find_max_feedback_phase_setting(program: &str) -> (Op, PhaseSettings) {
         }
     }
 
-    (max_thruster, phase_settings)
+    // Start a batch of loops before waiting on any, so they run at once
+    for batch in candidates.chunks(LOOPS_AT_ONCE) {
+        let mut loops = Vec::with_capacity(batch.len());
+        for test_settings in batch {
+            loops.push((test_settings, start_feedback(program, test_settings)?));
+        }
+
+        for (test_settings, network) in loops {
+            let test_signal = finish_feedback(network)?;
+            if max_thruster < test_signal {
+                max_thruster = test_signal;
+                phase_settings = *test_settings;
+            }
+        }
+    }
+
+    Ok((max_thruster, phase_settings))
 }
 
 #[cfg(test)]
@@ -117,66 +174,82 @@ mod tests {
     const PROGRAM_7: &str = "3,8,1001,8,10,8,105,1,0,0,21,34,47,72,81,102,183,264,345,426,99999,3,9,102,5,9,9,1001,9,3,9,4,9,99,3,9,101,4,9,9,1002,9,3,9,4,9,99,3,9,102,3,9,9,101,2,9,9,102,5,9,9,1001,9,3,9,1002,9,4,9,4,9,99,3,9,101,5,9,9,4,9,99,3,9,101,3,9,9,1002,9,5,9,101,4,9,9,102,2,9,9,4,9,99,3,9,1002,9,2,9,4,9,3,9,101,2,9,9,4,9,3,9,102,2,9,9,4,9,3,9,1002,9,2,9,4,9,3,9,1002,9,2,9,4,9,3,9,1002,9,2,9,4,9,3,9,1001,9,1,9,4,9,3,9,1001,9,1,9,4,9,3,9,1001,9,2,9,4,9,3,9,101,1,9,9,4,9,99,3,9,101,1,9,9,4,9,3,9,1001,9,2,9,4,9,3,9,102,2,9,9,4,9,3,9,102,2,9,9,4,9,3,9,1001,9,2,9,4,9,3,9,102,2,9,9,4,9,3,9,102,2,9,9,4,9,3,9,1001,9,2,9,4,9,3,9,1001,9,2,9,4,9,3,9,101,2,9,9,4,9,99,3,9,101,1,9,9,4,9,3,9,101,2,9,9,4,9,3,9,1001,9,1,9,4,9,3,9,101,2,9,9,4,9,3,9,1001,9,1,9,4,9,3,9,101,1,9,9,4,9,3,9,1002,9,2,9,4,9,3,9,1001,9,1,9,4,9,3,9,102,2,9,9,4,9,3,9,1002,9,2,9,4,9,99,3,9,1001,9,2,9,4,9,3,9,101,1,9,9,4,9,3,9,1001,9,1,9,4,9,3,9,102,2,9,9,4,9,3,9,101,1,9,9,4,9,3,9,1002,9,2,9,4,9,3,9,1001,9,1,9,4,9,3,9,101,2,9,9,4,9,3,9,101,2,9,9,4,9,3,9,102,2,9,9,4,9,99,3,9,102,2,9,9,4,9,3,9,1001,9,2,9,4,9,3,9,102,2,9,9,4,9,3,9,101,2,9,9,4,9,3,9,1001,9,1,9,4,9,3,9,102,2,9,9,4,9,3,9,1002,9,2,9,4,9,3,9,102,2,9,9,4,9,3,9,1002,9,2,9,4,9,3,9,1001,9,1,9,4,9,99";
 
     #[test]
-    fn example1() {
+    fn example1() -> Result<()> {
         let program = "3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0";
         let max = 43210;
         let phase_settings: PhaseSettings = [4, 3, 2, 1, 0];
 
-        let signal = thruster_signal(program, &phase_settings);
+        let signal = thruster_signal(program, &phase_settings)?;
         assert_eq!(max, signal);
+
+        Ok(())
     }
 
     #[test]
-    fn example1_find() {
+    fn example1_find() -> Result<()> {
         let program = "3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0";
         let expected_max = 43210;
         let expected_phase: PhaseSettings = [4, 3, 2, 1, 0];
 
-        let (actual_max, actual_phase) = find_max_phase_setting(program);
+        let (actual_max, actual_phase) = find_max_phase_setting(program)?;
 
         assert_eq!(actual_phase, expected_phase);
         assert_eq!(actual_max, expected_max);
+
+        Ok(())
     }
 
     #[test]
-    fn seven_1() {
+    fn seven_1() -> Result<()> {
         let expected_max = 92663;
         let expected_phase: PhaseSettings = [3, 1, 4, 2, 0];
 
-        let (actual_max, actual_phase) = find_max_phase_setting(PROGRAM_7);
+        let (actual_max, actual_phase) = find_max_phase_setting(PROGRAM_7)?;
 
         assert_eq!(actual_phase, expected_phase);
         assert_eq!(actual_max, expected_max);
+
+        Ok(())
     }
 
     #[test]
-    fn seven_2() {
+    fn seven_2() -> Result<()> {
         let expected_max = 14_365_052;
         let expected_phase: PhaseSettings = [7, 8, 6, 9, 5];
-        let (actual_max, actual_phase) = find_max_feedback_phase_setting(PROGRAM_7);
+        let (actual_max, actual_phase) = find_max_feedback_phase_setting(PROGRAM_7)?;
 
         assert_eq!(actual_max, expected_max);
         assert_eq!(actual_phase, expected_phase);
+        assert_eq!(feedback(PROGRAM_7, &expected_phase)?, expected_max);
+
+        Ok(())
     }
 
     #[test]
-    fn example_2_1() {
+    fn example_2_1() -> Result<()> {
         let program =
             "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
         let phase = [9, 8, 7, 6, 5];
 
-        let output = feedback(program, &phase);
+        let output = feedback(program, &phase)?;
         assert_eq!(output, 139_629_729);
+
+        let threaded = finish_feedback(start_feedback(program, &phase)?)?;
+        assert_eq!(threaded, output);
+
+        Ok(())
     }
 
     #[test]
-    fn example_2_1_find() {
+    fn example_2_1_find() -> Result<()> {
         let program =
             "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
         let expected_phase = [9, 8, 7, 6, 5];
 
-        let (max_thruster, phase_setting) = find_max_feedback_phase_setting(program);
+        let (max_thruster, phase_setting) = find_max_feedback_phase_setting(program)?;
         assert_eq!(max_thruster, 139_629_729);
         assert_eq!(phase_setting, expected_phase);
+
+        Ok(())
     }
 }
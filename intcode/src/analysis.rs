@@ -0,0 +1,502 @@
+use crate::{Instruction, Op, Param};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+use std::ops::Range;
+
+/// How control leaves a basic block
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Terminator {
+    /// Runs straight on into the block at this address
+    Fallthrough(usize),
+    Jump(usize),
+    /// Conditional jump with a static target
+    Branch {
+        taken: usize,
+        fallthrough: usize,
+    },
+    /// The call idiom: a return address stored to `[r+0]` right before an
+    /// unconditional jump. Execution is assumed to come back to `returns`.
+    Call {
+        target: usize,
+        returns: usize,
+    },
+    /// Unconditional jump through `[r+0]`, the other half of the idiom
+    Return,
+    /// Jump whose target is only known at run time. A conditional one can
+    /// also fall through.
+    Indirect {
+        fallthrough: Option<usize>,
+    },
+    Halt,
+    /// Next instruction doesn't decode or runs off the end of the image
+    Invalid,
+}
+
+impl Terminator {
+    /// Addresses control can statically be seen to go to
+    pub fn successors(&self) -> Vec<usize> {
+        match *self {
+            Terminator::Fallthrough(next) | Terminator::Jump(next) => vec![next],
+            Terminator::Branch { taken, fallthrough } => vec![taken, fallthrough],
+            Terminator::Call { target, returns } => vec![target, returns],
+            Terminator::Indirect { fallthrough } => fallthrough.into_iter().collect(),
+            Terminator::Return | Terminator::Halt | Terminator::Invalid => vec![],
+        }
+    }
+}
+
+/// Straight line code, only entered at `start`
+#[derive(PartialEq, Debug, Clone)]
+pub struct Block {
+    pub start: usize,
+    /// Empty for a jump target that doesn't decode
+    pub instructions: Vec<(usize, Instruction)>,
+    pub terminator: Terminator,
+}
+
+impl Block {
+    /// One past the block's last cell
+    pub fn end(&self) -> usize {
+        match self.instructions.last() {
+            Some((address, instruction)) => address + instruction.size(),
+            None => self.start,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Subroutine {
+    pub entry: usize,
+    /// Addresses of the jumps that call it
+    pub call_sites: Vec<usize>,
+    /// Starts of the blocks reachable from the entry without following
+    /// calls into other subroutines
+    pub blocks: Vec<usize>,
+}
+
+/// Control flow graph of a program image, see `analyze`
+#[derive(PartialEq, Debug, Clone)]
+pub struct ControlFlow {
+    /// In address order
+    pub blocks: Vec<Block>,
+    /// In entry order
+    pub subroutines: Vec<Subroutine>,
+    /// Parts of the image no reachable instruction covers
+    pub data: Vec<Range<usize>>,
+}
+
+/// Finds the code reachable from address 0 by following every statically
+/// known edge, and splits it into basic blocks. This only looks at the
+/// image, so code that's written at run time, or only reached through
+/// computed jumps other than subroutine returns, shows up as data.
+pub fn analyze(image: &[Op]) -> ControlFlow {
+    let mut sweep = Sweep {
+        image,
+        code: BTreeMap::new(),
+        exits: BTreeMap::new(),
+        invalid: BTreeSet::new(),
+        leaders: BTreeSet::new(),
+    };
+    sweep.explore(0);
+
+    let blocks = sweep.blocks();
+    let subroutines = subroutines(&blocks);
+    let data = sweep.data();
+    ControlFlow {
+        blocks,
+        subroutines,
+        data,
+    }
+}
+
+impl ControlFlow {
+    /// The block starting at `start`
+    pub fn block(&self, start: usize) -> Option<&Block> {
+        self.blocks
+            .binary_search_by_key(&start, |block| block.start)
+            .ok()
+            .map(|index| &self.blocks[index])
+    }
+
+    /// Renders the graph for Graphviz. Subroutines are drawn as clusters,
+    /// data regions as unconnected notes.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph intcode {\n");
+        dot.push_str("    node [shape=box fontname=\"monospace\"];\n");
+
+        // A node can only sit in one cluster, shared blocks go in the first
+        let mut placed = BTreeSet::new();
+        for subroutine in self.subroutines.iter() {
+            writeln!(dot, "    subgraph cluster_{} {{", subroutine.entry).unwrap();
+            writeln!(dot, "        label=\"sub {}\";", subroutine.entry).unwrap();
+            for &start in subroutine.blocks.iter() {
+                if placed.insert(start) {
+                    writeln!(dot, "        b{};", start).unwrap();
+                }
+            }
+            dot.push_str("    }\n");
+        }
+
+        for block in self.blocks.iter() {
+            let mut label = String::new();
+            for (address, instruction) in block.instructions.iter() {
+                write!(label, "{}: {}\\l", address, instruction).unwrap();
+            }
+            if block.instructions.is_empty() {
+                write!(label, "{}: invalid\\l", block.start).unwrap();
+            }
+            writeln!(dot, "    b{} [label=\"{}\"];", block.start, label).unwrap();
+
+            let from = block.start;
+            match block.terminator {
+                Terminator::Fallthrough(next) | Terminator::Jump(next) => {
+                    writeln!(dot, "    b{} -> b{};", from, next).unwrap()
+                }
+                Terminator::Branch { taken, fallthrough } => {
+                    writeln!(dot, "    b{} -> b{} [label=\"taken\"];", from, taken).unwrap();
+                    writeln!(dot, "    b{} -> b{} [label=\"else\"];", from, fallthrough).unwrap();
+                }
+                Terminator::Call { target, returns } => {
+                    writeln!(dot, "    b{} -> b{} [label=\"call\"];", from, target).unwrap();
+                    writeln!(dot, "    b{} -> b{} [style=dashed];", from, returns).unwrap();
+                }
+                Terminator::Indirect {
+                    fallthrough: Some(next),
+                } => writeln!(dot, "    b{} -> b{} [label=\"else\"];", from, next).unwrap(),
+                _ => {}
+            }
+        }
+
+        for range in self.data.iter() {
+            writeln!(
+                dot,
+                "    d{} [shape=note label=\"data {}..{}\"];",
+                range.start, range.start, range.end
+            )
+            .unwrap();
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+struct Sweep<'a> {
+    image: &'a [Op],
+    code: BTreeMap<usize, Instruction>,
+    /// Terminators, by the address of the instruction that ends the block
+    exits: BTreeMap<usize, Terminator>,
+    /// Reachable addresses that don't hold a whole instruction
+    invalid: BTreeSet<usize>,
+    leaders: BTreeSet<usize>,
+}
+
+impl<'a> Sweep<'a> {
+    fn decode(&self, address: usize) -> Option<Instruction> {
+        let read = |address: usize| self.image.get(address).copied().unwrap_or(0);
+        match Instruction::decode(address, read) {
+            Ok(instruction) if address + instruction.size() <= self.image.len() => {
+                Some(instruction)
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes forward from each address on the work list until control
+    /// leaves the straight line, queueing wherever it goes
+    fn explore(&mut self, entry: usize) {
+        let mut work = vec![entry];
+        self.leaders.insert(entry);
+        while let Some(mut address) = work.pop() {
+            let mut previous = None;
+            loop {
+                if self.code.contains_key(&address) || self.invalid.contains(&address) {
+                    // Joined code that's already been seen, so a block
+                    // starts here
+                    self.leaders.insert(address);
+                    break;
+                }
+
+                let instruction = match self.decode(address) {
+                    Some(instruction) => instruction,
+                    None => {
+                        self.invalid.insert(address);
+                        break;
+                    }
+                };
+
+                self.code.insert(address, instruction);
+                if let Some(terminator) = terminator(address, instruction, previous) {
+                    for next in terminator.successors() {
+                        self.leaders.insert(next);
+                        work.push(next);
+                    }
+                    self.exits.insert(address, terminator);
+                    break;
+                }
+
+                previous = Some(instruction);
+                address += instruction.size();
+            }
+        }
+    }
+
+    fn blocks(&self) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut current: Option<Block> = None;
+        for (&address, &instruction) in self.code.iter() {
+            let starts = match &current {
+                Some(block) => self.leaders.contains(&address) || block.end() != address,
+                None => true,
+            };
+            if starts {
+                if let Some(block) = current.take() {
+                    blocks.push(self.run_on(block));
+                }
+            }
+
+            let block = current.get_or_insert_with(|| Block {
+                start: address,
+                instructions: Vec::new(),
+                terminator: Terminator::Invalid,
+            });
+            block.instructions.push((address, instruction));
+            if let Some(&terminator) = self.exits.get(&address) {
+                block.terminator = terminator;
+                blocks.push(current.take().unwrap());
+            }
+        }
+
+        if let Some(block) = current {
+            blocks.push(self.run_on(block));
+        }
+
+        // Jumps straight to something undecodable still get a block, so
+        // every edge has somewhere to go
+        for &address in self.invalid.intersection(&self.leaders) {
+            blocks.push(Block {
+                start: address,
+                instructions: Vec::new(),
+                terminator: Terminator::Invalid,
+            });
+        }
+
+        blocks.sort_by_key(|block| block.start);
+        blocks
+    }
+
+    /// Finishes a block that ends without a control transfer of its own
+    fn run_on(&self, mut block: Block) -> Block {
+        let next = block.end();
+        if self.leaders.contains(&next) {
+            block.terminator = Terminator::Fallthrough(next);
+        }
+
+        block
+    }
+
+    fn data(&self) -> Vec<Range<usize>> {
+        let mut covered = vec![false; self.image.len()];
+        for (&address, instruction) in self.code.iter() {
+            for cell in covered.iter_mut().skip(address).take(instruction.size()) {
+                *cell = true;
+            }
+        }
+
+        let mut data: Vec<Range<usize>> = Vec::new();
+        for (address, _) in covered.iter().enumerate().filter(|(_, &code)| !code) {
+            match data.last_mut() {
+                Some(range) if range.end == address => range.end += 1,
+                _ => data.push(address..address + 1),
+            }
+        }
+
+        data
+    }
+}
+
+/// How the instruction at `address` ends its block, if it does
+fn terminator(
+    address: usize,
+    instruction: Instruction,
+    previous: Option<Instruction>,
+) -> Option<Terminator> {
+    let next = address + instruction.size();
+    let (condition, target, on_true) = match instruction {
+        Instruction::Halt => return Some(Terminator::Halt),
+        Instruction::JumpIfTrue(condition, target) => (condition, target, true),
+        Instruction::JumpIfFalse(condition, target) => (condition, target, false),
+        _ => return None,
+    };
+
+    let always = match condition {
+        Param::Immediate(value) => Some((value != 0) == on_true),
+        _ => None,
+    };
+    let target = match target {
+        Param::Immediate(target) if target >= 0 => Some(target as usize),
+        Param::Relative(0) if always == Some(true) => return Some(Terminator::Return),
+        _ => None,
+    };
+
+    Some(match (always, target) {
+        (Some(false), _) => Terminator::Fallthrough(next),
+        (Some(true), Some(target)) if pushes(previous, next) => Terminator::Call {
+            target,
+            returns: next,
+        },
+        (Some(true), Some(target)) => Terminator::Jump(target),
+        (Some(true), None) => Terminator::Indirect { fallthrough: None },
+        (None, Some(target)) => Terminator::Branch {
+            taken: target,
+            fallthrough: next,
+        },
+        (None, None) => Terminator::Indirect {
+            fallthrough: Some(next),
+        },
+    })
+}
+
+/// Whether `instruction` stores the constant `value` to `[r+0]`
+fn pushes(instruction: Option<Instruction>, value: usize) -> bool {
+    let stored = match instruction {
+        Some(Instruction::Add(Param::Immediate(a), Param::Immediate(b), Param::Relative(0))) => {
+            a.checked_add(b)
+        }
+        Some(Instruction::Multiply(
+            Param::Immediate(a),
+            Param::Immediate(b),
+            Param::Relative(0),
+        )) => a.checked_mul(b),
+        _ => None,
+    };
+
+    stored == Some(value as Op)
+}
+
+fn subroutines(blocks: &[Block]) -> Vec<Subroutine> {
+    let mut calls: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for block in blocks.iter() {
+        if let (Terminator::Call { target, .. }, Some((site, _))) =
+            (block.terminator, block.instructions.last())
+        {
+            calls.entry(target).or_default().push(*site);
+        }
+    }
+
+    let by_start: BTreeMap<usize, &Block> =
+        blocks.iter().map(|block| (block.start, block)).collect();
+    calls
+        .into_iter()
+        .map(|(entry, call_sites)| {
+            let mut seen = BTreeSet::new();
+            let mut queue = VecDeque::new();
+            queue.push_back(entry);
+            while let Some(start) = queue.pop_front() {
+                let block = match by_start.get(&start) {
+                    Some(block) if seen.insert(start) => block,
+                    _ => continue,
+                };
+
+                match block.terminator {
+                    Terminator::Call { returns, .. } => queue.push_back(returns),
+                    terminator => queue.extend(terminator.successors()),
+                }
+            }
+
+            Subroutine {
+                entry,
+                call_sites,
+                blocks: seen.into_iter().collect(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_program, IntCode, Status};
+
+    // Calls a subroutine that outputs 5, using the same calling convention
+    // as the day 9 program
+    const CALL: &str = "109,100,21101,0,9,0,1105,1,11,99,42,104,5,2105,1,0";
+
+    fn starts(flow: &ControlFlow) -> Vec<usize> {
+        flow.blocks.iter().map(|block| block.start).collect()
+    }
+
+    #[test]
+    fn branches() {
+        let flow = analyze(&parse_program("3,0,1005,0,7,104,0,99").unwrap());
+        assert_eq!(starts(&flow), vec![0, 5, 7]);
+        assert_eq!(
+            flow.blocks[0].terminator,
+            Terminator::Branch {
+                taken: 7,
+                fallthrough: 5
+            }
+        );
+        assert_eq!(flow.blocks[1].terminator, Terminator::Fallthrough(7));
+        assert_eq!(flow.blocks[2].terminator, Terminator::Halt);
+        assert!(flow.data.is_empty());
+    }
+
+    #[test]
+    fn call_idiom() {
+        let mut computer = IntCode::new(CALL).unwrap();
+        assert_eq!(computer.run(), Ok(Status::Halted));
+        assert_eq!(computer.output(), &vec![5]);
+
+        let flow = analyze(&parse_program(CALL).unwrap());
+        assert_eq!(starts(&flow), vec![0, 9, 11]);
+        assert_eq!(
+            flow.blocks[0].terminator,
+            Terminator::Call {
+                target: 11,
+                returns: 9
+            }
+        );
+        assert_eq!(flow.block(11).unwrap().terminator, Terminator::Return);
+        assert_eq!(
+            flow.subroutines,
+            vec![Subroutine {
+                entry: 11,
+                call_sites: vec![6],
+                blocks: vec![11],
+            }]
+        );
+        assert_eq!(flow.data, vec![10..11]);
+    }
+
+    #[test]
+    fn indirect_and_invalid() {
+        // Jumps to wherever input says, otherwise falls into a bad opcode
+        let flow = analyze(&parse_program("3,6,5,6,6,42,0").unwrap());
+        assert_eq!(
+            flow.blocks[0].terminator,
+            Terminator::Indirect {
+                fallthrough: Some(5)
+            }
+        );
+        assert_eq!(flow.block(5).unwrap().instructions, vec![]);
+        assert_eq!(flow.block(5).unwrap().terminator, Terminator::Invalid);
+        assert_eq!(flow.data, vec![5..7]);
+    }
+
+    #[test]
+    fn dot() {
+        let dot = analyze(&parse_program(CALL).unwrap()).to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains(
+            "    subgraph cluster_11 {\n        label=\"sub 11\";\n        b11;\n    }\n"
+        ));
+        assert!(dot.contains(
+            "    b0 [label=\"0: ARB #100\\l2: ADD #0, #9 -> [r+0]\\l6: JT #1, #11\\l\"];\n"
+        ));
+        assert!(dot.contains("    b0 -> b11 [label=\"call\"];\n    b0 -> b9 [style=dashed];\n"));
+        assert!(dot.contains("    d10 [shape=note label=\"data 10..11\"];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
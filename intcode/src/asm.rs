@@ -0,0 +1,574 @@
+//! A small assembler for intcode. One instruction per line:
+//!
+//! ```text
+//! ; echo until a zero is read
+//! loop:   in [x]
+//!         jf [x], #done
+//!         out [x]
+//!         jt #1, #loop
+//! done:   hlt
+//! x:      db 0
+//! ```
+//!
+//! Operands are `#value` (immediate), `[address]` (position) or
+//! `[r+offset]` (relative). Values are integers, labels or `label+n`.
+//! The destination operand may be separated by `->` instead of a comma,
+//! so disassembler output assembles back to the same image.
+
+use crate::instruction::{
+    ADD, ADJUST_RELATIVE_BASE, EQUALS, HCF, INPUT, JUMP_IF_FALSE, JUMP_IF_TRUE, LESS_THAN,
+    MULTIPLY, OUTPUT,
+};
+use crate::{Instruction, Op, Ops, Param};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// Assembly failure, `line` and `column` are 1 based
+#[derive(PartialEq, Debug, Clone)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+type AsmResult<T> = std::result::Result<T, AsmError>;
+
+#[derive(PartialEq, Debug, Clone)]
+enum Token {
+    Ident(String),
+    Number(Op),
+    Hash,
+    Open,
+    Close,
+    Comma,
+    Arrow,
+    Colon,
+    Plus,
+    Minus,
+}
+
+/// Integer or label reference, plus a constant offset
+#[derive(Debug, Clone)]
+struct Expr {
+    label: Option<(String, usize)>,
+    offset: Op,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+#[derive(Debug, Clone)]
+struct Operand {
+    mode: Mode,
+    value: Expr,
+    column: usize,
+}
+
+#[derive(Debug)]
+enum Item {
+    Instruction { opcode: Op, operands: Vec<Operand> },
+    Data(Vec<Expr>),
+}
+
+/// Assembles source text into a program image
+pub fn assemble(source: &str) -> AsmResult<Ops> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut items = Vec::new();
+    let mut address = 0;
+
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let mut parser = Parser {
+            tokens: tokenize(line, text)?,
+            pos: 0,
+            line,
+            end: text.chars().count() + 1,
+        };
+
+        while let Some((name, column)) = parser.label() {
+            if labels.insert(name.clone(), address).is_some() {
+                return Err(error(line, column, format!("duplicate label `{}`", name)));
+            }
+        }
+
+        if let Some(item) = parser.item()? {
+            address += match &item {
+                Item::Instruction { operands, .. } => 1 + operands.len(),
+                Item::Data(values) => values.len(),
+            };
+            items.push((line, item));
+        }
+    }
+
+    let mut ops = Ops::with_capacity(address);
+    for (line, item) in items {
+        let resolve = |expr: &Expr| resolve(&labels, line, expr);
+        match item {
+            Item::Data(values) => {
+                for value in values.iter() {
+                    ops.push(resolve(value)?);
+                }
+            }
+            Item::Instruction { opcode, operands } => {
+                let mut params = Vec::with_capacity(operands.len());
+                for operand in operands.iter() {
+                    let value = resolve(&operand.value)?;
+                    params.push(match operand.mode {
+                        Mode::Position => Param::Position(value),
+                        Mode::Immediate => Param::Immediate(value),
+                        Mode::Relative => Param::Relative(value),
+                    });
+                }
+
+                ops.extend(build(opcode, &params).encode());
+            }
+        }
+    }
+
+    Ok(ops)
+}
+
+fn build(opcode: Op, p: &[Param]) -> Instruction {
+    match opcode {
+        ADD => Instruction::Add(p[0], p[1], p[2]),
+        MULTIPLY => Instruction::Multiply(p[0], p[1], p[2]),
+        INPUT => Instruction::Input(p[0]),
+        OUTPUT => Instruction::Output(p[0]),
+        JUMP_IF_TRUE => Instruction::JumpIfTrue(p[0], p[1]),
+        JUMP_IF_FALSE => Instruction::JumpIfFalse(p[0], p[1]),
+        LESS_THAN => Instruction::LessThan(p[0], p[1], p[2]),
+        EQUALS => Instruction::Equals(p[0], p[1], p[2]),
+        ADJUST_RELATIVE_BASE => Instruction::AdjustRelativeBase(p[0]),
+        _ => Instruction::Halt,
+    }
+}
+
+/// Opcode, operand count and whether the last operand is written to
+fn lookup(mnemonic: &str) -> Option<(Op, usize, bool)> {
+    let entry = match mnemonic.to_lowercase().as_str() {
+        "add" => (ADD, 3, true),
+        "mul" => (MULTIPLY, 3, true),
+        "in" => (INPUT, 1, true),
+        "out" => (OUTPUT, 1, false),
+        "jt" => (JUMP_IF_TRUE, 2, false),
+        "jf" => (JUMP_IF_FALSE, 2, false),
+        "lt" => (LESS_THAN, 3, true),
+        "eq" => (EQUALS, 3, true),
+        "arb" => (ADJUST_RELATIVE_BASE, 1, false),
+        "hlt" => (HCF, 0, false),
+        _ => return None,
+    };
+
+    Some(entry)
+}
+
+fn resolve(labels: &HashMap<String, usize>, line: usize, expr: &Expr) -> AsmResult<Op> {
+    match &expr.label {
+        None => Ok(expr.offset),
+        Some((name, column)) => match labels.get(name) {
+            Some(&address) => (address as Op).checked_add(expr.offset).ok_or_else(|| {
+                error(
+                    line,
+                    *column,
+                    format!("`{}` plus its offset overflows", name),
+                )
+            }),
+            None => Err(error(line, *column, format!("undefined label `{}`", name))),
+        },
+    }
+}
+
+fn error(line: usize, column: usize, message: String) -> AsmError {
+    AsmError {
+        line,
+        column,
+        message,
+    }
+}
+
+fn tokenize(line: usize, text: &str) -> AsmResult<Vec<(Token, usize)>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        let token = match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '#' => Token::Hash,
+            '[' => Token::Open,
+            ']' => Token::Close,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '+' => Token::Plus,
+            '-' if chars.get(i + 1) == Some(&'>') => {
+                i += 1;
+                Token::Arrow
+            }
+            '-' => Token::Minus,
+            c if c.is_ascii_digit() => {
+                let start = i;
+                while i + 1 < chars.len() && chars[i + 1].is_ascii_digit() {
+                    i += 1;
+                }
+
+                let digits: String = chars[start..=i].iter().collect();
+                match digits.parse() {
+                    Ok(number) => Token::Number(number),
+                    Err(_) => return Err(error(line, column, format!("{} is too large", digits))),
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let start = i;
+                while i + 1 < chars.len()
+                    && (chars[i + 1].is_ascii_alphanumeric() || chars[i + 1] == '_')
+                {
+                    i += 1;
+                }
+
+                Token::Ident(chars[start..=i].iter().collect())
+            }
+            _ => return Err(error(line, column, format!("unexpected `{}`", c))),
+        };
+
+        tokens.push((token, column));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    line: usize,
+    /// Column reported for errors at end of line
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|&(_, column)| column)
+            .unwrap_or(self.end)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn fail<T>(&self, message: String) -> AsmResult<T> {
+        Err(error(self.line, self.column(), message))
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> AsmResult<()> {
+        if self.peek() == Some(&expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.fail(format!("expected {}", what))
+        }
+    }
+
+    /// Consumes a leading `name:` if there is one
+    fn label(&mut self) -> Option<(String, usize)> {
+        match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some((Token::Ident(name), column)), Some((Token::Colon, _))) => {
+                let label = (name.clone(), *column);
+                self.pos += 2;
+                Some(label)
+            }
+            _ => None,
+        }
+    }
+
+    fn item(&mut self) -> AsmResult<Option<Item>> {
+        let column = self.column();
+        let mnemonic = match self.next() {
+            None => return Ok(None),
+            Some(Token::Ident(mnemonic)) => mnemonic,
+            Some(_) => return Err(error(self.line, column, "expected mnemonic".to_string())),
+        };
+
+        if mnemonic.eq_ignore_ascii_case("db") {
+            let mut values = vec![self.expr()?];
+            while self.peek() == Some(&Token::Comma) {
+                self.pos += 1;
+                values.push(self.expr()?);
+            }
+
+            self.finish()?;
+            return Ok(Some(Item::Data(values)));
+        }
+
+        let (opcode, count, writes) = match lookup(&mnemonic) {
+            Some(entry) => entry,
+            None => {
+                return Err(error(
+                    self.line,
+                    column,
+                    format!("unknown mnemonic `{}`", mnemonic),
+                ))
+            }
+        };
+
+        let mut operands = Vec::with_capacity(count);
+        for i in 0..count {
+            if i > 0 {
+                match self.peek() {
+                    Some(Token::Comma) => self.pos += 1,
+                    Some(Token::Arrow) if writes && i == count - 1 => self.pos += 1,
+                    _ => return self.fail(format!("`{}` takes {} operands", mnemonic, count)),
+                }
+            } else if writes && count == 1 && self.peek() == Some(&Token::Arrow) {
+                self.pos += 1;
+            }
+
+            let operand = self.operand()?;
+            if writes && i == count - 1 && operand.mode == Mode::Immediate {
+                return Err(error(
+                    self.line,
+                    operand.column,
+                    "can't write to an immediate operand".to_string(),
+                ));
+            }
+
+            operands.push(operand);
+        }
+
+        if self.peek() == Some(&Token::Comma) {
+            return self.fail(format!("`{}` takes {} operands", mnemonic, count));
+        }
+
+        self.finish()?;
+
+        Ok(Some(Item::Instruction { opcode, operands }))
+    }
+
+    fn finish(&self) -> AsmResult<()> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => self.fail("unexpected trailing input".to_string()),
+        }
+    }
+
+    fn operand(&mut self) -> AsmResult<Operand> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::Hash) => Ok(Operand {
+                mode: Mode::Immediate,
+                value: self.expr()?,
+                column,
+            }),
+            Some(Token::Open) => {
+                let relative = matches!(self.peek(), Some(Token::Ident(name)) if name == "r");
+                let operand = if relative {
+                    self.pos += 1;
+                    let value = match self.peek() {
+                        Some(Token::Plus) => {
+                            self.pos += 1;
+                            self.expr()?
+                        }
+                        Some(Token::Minus) => {
+                            self.pos += 1;
+                            let number = self.number()?;
+                            Expr {
+                                label: None,
+                                offset: -number,
+                            }
+                        }
+                        _ => Expr {
+                            label: None,
+                            offset: 0,
+                        },
+                    };
+
+                    Operand {
+                        mode: Mode::Relative,
+                        value,
+                        column,
+                    }
+                } else {
+                    Operand {
+                        mode: Mode::Position,
+                        value: self.expr()?,
+                        column,
+                    }
+                };
+
+                self.expect(Token::Close, "`]`")?;
+                Ok(operand)
+            }
+            _ => Err(error(
+                self.line,
+                column,
+                "expected `#value`, `[address]` or `[r+offset]`".to_string(),
+            )),
+        }
+    }
+
+    fn number(&mut self) -> AsmResult<Op> {
+        match self.peek() {
+            Some(&Token::Number(number)) => {
+                self.pos += 1;
+                Ok(number)
+            }
+            _ => self.fail("expected a number".to_string()),
+        }
+    }
+
+    /// `[-]number`, `label`, or `label (+|-) number`
+    fn expr(&mut self) -> AsmResult<Expr> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::Minus) => Ok(Expr {
+                label: None,
+                offset: -self.number()?,
+            }),
+            Some(Token::Number(number)) => Ok(Expr {
+                label: None,
+                offset: number,
+            }),
+            Some(Token::Ident(name)) if name != "r" => {
+                let offset = match self.peek() {
+                    Some(Token::Plus) => {
+                        self.pos += 1;
+                        self.number()?
+                    }
+                    Some(Token::Minus) => {
+                        self.pos += 1;
+                        -self.number()?
+                    }
+                    _ => 0,
+                };
+
+                Ok(Expr {
+                    label: Some((name, column)),
+                    offset,
+                })
+            }
+            _ => Err(error(self.line, column, "expected a value".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{disassemble, parse_program, IntCode, Status};
+
+    #[test]
+    fn equal_to_8() {
+        let source = "
+                in [x]
+                eq [x], [eight] -> [x]
+                out [x]
+                hlt
+            x:  db -1
+            eight: db 8";
+        let expected = parse_program("3,9,8,9,10,9,4,9,99,-1,8").unwrap();
+        assert_eq!(assemble(source), Ok(expected));
+    }
+
+    #[test]
+    fn less_than_8_immediate() {
+        let source = "
+                in [3]
+                lt #-1, #8 -> [3]   ; first operand is overwritten by the input
+                out [3]
+                hlt";
+        let expected = parse_program("3,3,1107,-1,8,3,4,3,99").unwrap();
+        assert_eq!(assemble(source), Ok(expected));
+    }
+
+    #[test]
+    fn labels_and_loops() {
+        let source = "
+            loop:   in [x]
+                    jf [x], #done
+                    out [x]
+                    jt #1, #loop
+            done:   hlt
+            x:      db 0";
+        let mut computer = IntCode::init(assemble(source).unwrap());
+        for &input in [4, 5, 0].iter() {
+            computer.input(input);
+        }
+
+        assert_eq!(computer.run(), Ok(Status::Halted));
+        assert_eq!(computer.output(), &vec![4, 5]);
+    }
+
+    #[test]
+    fn relative_and_offsets() {
+        let source = "
+                arb #table
+                out [r+1]
+                out [r-0]
+                out [table+2]
+                hlt
+        table:  db 7, 8, 9";
+        let mut computer = IntCode::init(assemble(source).unwrap());
+        computer.run().unwrap();
+        assert_eq!(computer.output(), &vec![8, 7, 9]);
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        let ops =
+            parse_program("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99").unwrap();
+        let source: Vec<String> = disassemble(&ops)
+            .iter()
+            .map(|(_, instruction)| instruction.to_string())
+            .collect();
+        assert_eq!(assemble(&source.join("\n")), Ok(ops));
+    }
+
+    fn error_at(source: &str) -> (usize, usize) {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.column)
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error_at("hlt\nfoo #1"), (2, 1));
+        assert_eq!(error_at("out #1\n  jt #1, #nowhere"), (2, 11));
+        assert_eq!(error_at("add #1, #2"), (1, 11));
+        assert_eq!(error_at("add #1, #2, #3"), (1, 13));
+        assert_eq!(error_at("out [5"), (1, 7));
+        assert_eq!(error_at("a: hlt\na: hlt"), (2, 1));
+        assert_eq!(error_at("out 5"), (1, 5));
+        assert_eq!(error_at("db 1 2"), (1, 6));
+        assert_eq!(error_at("out #1, #2"), (1, 7));
+        assert_eq!(error_at("out #1 $"), (1, 8));
+        assert_eq!(
+            error_at("out #1\na: jt #1, #a+9223372036854775807"),
+            (2, 12)
+        );
+    }
+}
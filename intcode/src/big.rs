@@ -0,0 +1,307 @@
+use crate::{Instruction, Op, Param, Result, VmError, DEFAULT_MEMORY_LIMIT};
+use num_traits::{ToPrimitive, Zero};
+use std::collections::{HashMap, VecDeque};
+
+pub use num_bigint::BigInt;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum BigStatus {
+    Output(BigInt),
+    NeedsInput,
+    Halted,
+}
+
+/// An interpreter whose cells are arbitrary precision integers, so nothing
+/// ever overflows. Much slower than `IntCode`, and without its extras, but
+/// it runs the same programs and stops and faults the same way.
+#[derive(Clone, Debug)]
+pub struct BigIntCode {
+    image: Vec<BigInt>,
+    sparse: HashMap<usize, BigInt>,
+    limit: usize,
+    ip: usize,
+    relative_base: BigInt,
+    input: VecDeque<BigInt>,
+    output: Vec<BigInt>,
+}
+
+impl BigIntCode {
+    pub fn new(program: &str) -> Result<BigIntCode> {
+        let mut ops = Vec::new();
+        for (index, token) in program.split(',').map(str::trim).enumerate() {
+            let op = token.parse().map_err(|_| VmError::Parse {
+                index,
+                token: token.to_string(),
+            })?;
+            ops.push(op);
+        }
+
+        Ok(BigIntCode::init(ops))
+    }
+
+    pub fn init(image: Vec<BigInt>) -> BigIntCode {
+        BigIntCode {
+            image,
+            sparse: HashMap::new(),
+            limit: DEFAULT_MEMORY_LIMIT,
+            ip: 0,
+            relative_base: BigInt::zero(),
+            input: VecDeque::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn input<T: Into<BigInt>>(&mut self, value: T) {
+        self.input.push_back(value.into());
+    }
+
+    pub fn output(&self) -> &[BigInt] {
+        &self.output
+    }
+
+    pub fn get(&self, address: usize) -> BigInt {
+        match self.image.get(address) {
+            Some(value) => value.clone(),
+            None => self.sparse.get(&address).cloned().unwrap_or_default(),
+        }
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn set_memory_limit(&mut self, cells: usize) {
+        self.limit = cells;
+    }
+
+    /// Runs until the program halts or blocks waiting for input
+    pub fn run(&mut self) -> Result<BigStatus> {
+        loop {
+            match self.resume()? {
+                BigStatus::Output(_) => {}
+                status => return Ok(status),
+            }
+        }
+    }
+
+    /// Same contract as `IntCode::resume`
+    pub fn resume(&mut self) -> Result<BigStatus> {
+        loop {
+            let ip = self.ip;
+            let opcode = self.get(ip);
+            let opcode = opcode.to_i64().ok_or_else(|| VmError::BigOpcode {
+                ip,
+                opcode: opcode.to_string(),
+            })?;
+            // Operands too big for an `Op` decode as 0, they're read below
+            let instruction =
+                Instruction::decode(ip, |address| self.get(address).to_i64().unwrap_or(0))?;
+            let params = instruction.params();
+            let next =
+                || (ip.checked_add(instruction.size())).ok_or(VmError::IpOverflow { ip, opcode });
+
+            match instruction {
+                Instruction::Add(..)
+                | Instruction::Multiply(..)
+                | Instruction::LessThan(..)
+                | Instruction::Equals(..) => {
+                    let a = self.read(opcode, 1, params[0])?;
+                    let b = self.read(opcode, 2, params[1])?;
+                    let value = match instruction {
+                        Instruction::Add(..) => a + b,
+                        Instruction::Multiply(..) => a * b,
+                        Instruction::LessThan(..) => BigInt::from((a < b) as Op),
+                        _ => BigInt::from((a == b) as Op),
+                    };
+                    self.write(opcode, 3, params[2], value)?;
+                }
+                Instruction::Input(param) => {
+                    let value = match self.input.front() {
+                        Some(value) => value.clone(),
+                        None => return Ok(BigStatus::NeedsInput),
+                    };
+                    self.write(opcode, 1, param, value)?;
+                    self.input.pop_front();
+                }
+                Instruction::Output(param) => {
+                    let value = self.read(opcode, 1, param)?;
+                    self.ip = next()?;
+                    self.output.push(value.clone());
+                    return Ok(BigStatus::Output(value));
+                }
+                Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) => {
+                    let condition = !self.read(opcode, 1, params[0])?.is_zero();
+                    let target = self.read(opcode, 2, params[1])?;
+                    if condition == matches!(instruction, Instruction::JumpIfTrue(..)) {
+                        self.ip = self.address(opcode, &target, &target)?;
+                        continue;
+                    }
+                }
+                Instruction::AdjustRelativeBase(param) => {
+                    self.relative_base += self.read(opcode, 1, param)?;
+                }
+                Instruction::Halt => return Ok(BigStatus::Halted),
+                Instruction::Data(_) => unreachable!("decode never yields data"),
+            }
+
+            self.ip = next()?;
+        }
+    }
+
+    /// Value of the `n`th parameter
+    fn read(&self, coded_op: Op, n: usize, param: Param) -> Result<BigInt> {
+        let operand = self.get(self.ip + n);
+        match param {
+            Param::Position(_) => Ok(self.get(self.address(coded_op, &operand, &operand)?)),
+            Param::Immediate(_) => Ok(operand),
+            Param::Relative(_) => {
+                let address = &self.relative_base + &operand;
+                Ok(self.get(self.address(coded_op, &operand, &address)?))
+            }
+        }
+    }
+
+    /// Stores into the cell the `n`th parameter refers to
+    fn write(&mut self, coded_op: Op, n: usize, param: Param, value: BigInt) -> Result<()> {
+        let operand = self.get(self.ip + n);
+        let address = match param {
+            Param::Position(_) => self.address(coded_op, &operand, &operand)?,
+            Param::Immediate(_) => {
+                return Err(VmError::ImmediateWrite {
+                    ip: self.ip,
+                    opcode: coded_op,
+                    operand: clamp(&operand),
+                })
+            }
+            Param::Relative(_) => {
+                let address = &self.relative_base + &operand;
+                self.address(coded_op, &operand, &address)?
+            }
+        };
+
+        if let Some(cell) = self.image.get_mut(address) {
+            *cell = value;
+        } else if let Some(cell) = self.sparse.get_mut(&address) {
+            *cell = value;
+        } else if self.image.len() + self.sparse.len() >= self.limit {
+            return Err(self.memory_limit(coded_op, &operand, address));
+        } else {
+            self.sparse.insert(address, value);
+        }
+
+        Ok(())
+    }
+
+    fn address(&self, coded_op: Op, operand: &BigInt, address: &BigInt) -> Result<usize> {
+        if address < &BigInt::zero() {
+            return Err(VmError::NegativeAddress {
+                ip: self.ip,
+                opcode: coded_op,
+                operand: clamp(operand),
+                address: clamp(address),
+            });
+        }
+
+        address
+            .to_usize()
+            .ok_or_else(|| self.memory_limit(coded_op, operand, usize::MAX))
+    }
+
+    fn memory_limit(&self, coded_op: Op, operand: &BigInt, address: usize) -> VmError {
+        VmError::MemoryLimit {
+            ip: self.ip,
+            opcode: coded_op,
+            operand: clamp(operand),
+            address,
+            limit: self.limit,
+        }
+    }
+}
+
+/// Nearest `Op` for error reporting
+fn clamp(value: &BigInt) -> Op {
+    value.to_i64().unwrap_or(if value < &BigInt::zero() {
+        Op::MIN
+    } else {
+        Op::MAX
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outputs(program: &str, input: Op) -> Vec<String> {
+        let mut computer = BigIntCode::new(program).unwrap();
+        computer.input(input);
+        assert_eq!(computer.run(), Ok(BigStatus::Halted));
+        computer.output().iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn no_overflow() {
+        // Squares the input three times
+        let program = "3,0,2,0,0,0,2,0,0,0,2,0,0,0,4,0,99";
+        assert_eq!(
+            outputs(program, 1 << 20),
+            vec!["1461501637330902918203684832716283019655932542976"]
+        );
+    }
+
+    #[test]
+    fn nine_examples() {
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        assert_eq!(outputs(quine, 0).join(","), quine);
+        assert_eq!(
+            outputs("1102,34915192,34915192,7,4,7,99,0", 0),
+            vec!["1219070632396864"]
+        );
+        assert_eq!(
+            outputs("104,1125899906842624,99", 0),
+            vec!["1125899906842624"]
+        );
+    }
+
+    #[test]
+    fn faults_match_intcode() {
+        let mut computer = BigIntCode::new("1101,20,22,4,0").unwrap();
+        assert_eq!(
+            computer.run(),
+            Err(VmError::UnknownOpcode {
+                ip: 4,
+                opcode: 42,
+                operand: Some(0)
+            })
+        );
+
+        let mut computer = BigIntCode::new("1105,1,3,100000000000000000000").unwrap();
+        assert_eq!(
+            computer.run(),
+            Err(VmError::BigOpcode {
+                ip: 3,
+                opcode: "100000000000000000000".to_string()
+            })
+        );
+
+        // Runs an add from the very top of memory
+        let top = usize::MAX - 1;
+        let program = format!("1101,1,0,{0},1105,1,{0}", top);
+        let mut computer = BigIntCode::new(&program).unwrap();
+        assert_eq!(
+            computer.run(),
+            Err(VmError::IpOverflow { ip: top, opcode: 1 })
+        );
+
+        let mut computer = BigIntCode::new("3,-1").unwrap();
+        computer.input(1);
+        assert_eq!(
+            computer.run(),
+            Err(VmError::NegativeAddress {
+                ip: 0,
+                opcode: 3,
+                operand: -1,
+                address: -1
+            })
+        );
+    }
+}
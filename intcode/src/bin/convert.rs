@@ -0,0 +1,138 @@
+use intcode::{analyze, is_binary, read_program, to_binary, to_text, Op};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::{env, process};
+
+const USAGE: &str = "\
+usage: intcode-convert [options] <input|-> [output]
+
+Converts an intcode program between comma separated text and the binary
+image format. The input's format is detected, and by default it's written
+out in the other one. Output goes to stdout if no file is given.
+
+  -t, --text     always write text
+  -b, --binary   always write a binary image
+  -g, --graph    write the program's control flow graph as Graphviz DOT
+  -h, --help     show this";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Format {
+    Text,
+    Binary,
+    Graph,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    input: String,
+    output: Option<String>,
+    format: Option<Format>,
+}
+
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut paths = Vec::new();
+    let mut format = None;
+    for arg in args {
+        match arg.as_str() {
+            "-t" | "--text" => format = Some(Format::Text),
+            "-b" | "--binary" => format = Some(Format::Binary),
+            "-g" | "--graph" => format = Some(Format::Graph),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("unknown option {}\n\n{}", flag, USAGE))
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() > 2 {
+        return Err(format!("unexpected argument {}", paths[2]));
+    }
+
+    let mut paths = paths.into_iter();
+    Ok(Options {
+        input: paths.next().ok_or_else(|| USAGE.to_string())?,
+        output: paths.next(),
+        format,
+    })
+}
+
+/// Reads a program in either form and encodes it in `format`, or in
+/// whichever form it wasn't in
+fn convert(bytes: &[u8], format: Option<Format>) -> Result<Vec<u8>, String> {
+    let ops: Vec<Op> = read_program(bytes).map_err(|error| error.to_string())?;
+    let format = format.unwrap_or(if is_binary(bytes) {
+        Format::Text
+    } else {
+        Format::Binary
+    });
+
+    Ok(match format {
+        Format::Text => format!("{}\n", to_text(&ops)).into_bytes(),
+        Format::Binary => to_binary(&ops),
+        Format::Graph => analyze(&ops).to_dot().into_bytes(),
+    })
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    let read = if options.input == "-" {
+        io::stdin().read_to_end(&mut bytes)
+    } else {
+        File::open(&options.input).and_then(|mut file| file.read_to_end(&mut bytes))
+    };
+    read.map_err(|error| format!("{}: {}", options.input, error))?;
+
+    let converted = convert(&bytes, options.format)
+        .map_err(|message| format!("{}: {}", options.input, message))?;
+
+    let written = match &options.output {
+        Some(path) => File::create(path).and_then(|mut file| file.write_all(&converted)),
+        None => io::stdout().write_all(&converted),
+    };
+    written.map_err(|error| error.to_string())
+}
+
+fn main() {
+    let result = parse_args(env::args().skip(1)).and_then(|options| run(&options));
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(
+            args(&["-b", "day.txt", "day.bin"]),
+            Ok(Options {
+                input: "day.txt".to_string(),
+                output: Some("day.bin".to_string()),
+                format: Some(Format::Binary),
+            })
+        );
+        assert_eq!(args(&["-"]).unwrap().output, None);
+        assert!(args(&[]).is_err());
+        assert!(args(&["a", "b", "c"]).is_err());
+    }
+
+    #[test]
+    fn converts_to_other_form() {
+        let binary = convert(b"104,-7,99\n", None).unwrap();
+        assert_eq!(binary, to_binary(&[104, -7, 99]));
+        assert_eq!(convert(&binary, None), Ok(b"104,-7,99\n".to_vec()));
+        assert_eq!(convert(&binary, Some(Format::Binary)), Ok(binary));
+        assert!(convert(b"1,2,three", None).is_err());
+
+        let graph = convert(b"104,-7,99", Some(Format::Graph)).unwrap();
+        assert!(String::from_utf8(graph).unwrap().contains("b0 [label="));
+    }
+}
@@ -0,0 +1,434 @@
+use intcode::{disassemble, IntCode, Op, Status};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::{env, process};
+
+const HELP: &str = "\
+s, step [n]        execute n instructions (default 1)
+c, continue        run until a breakpoint, watchpoint, input wait, halt or fault
+b, break [addr]    set a breakpoint, or list them
+d, delete addr     remove a breakpoint or watchpoint
+w, watch [addr]    stop after writes to a cell, or list watched cells
+r, regs            print ip, relative base and queued input
+stats              print instruction counts and other execution statistics
+diff               show memory changed since loading, and writes into code
+m addr [n]         dump n cells in decimal (default 16)
+x addr [n]         dump n cells in hex
+l, dis [n]         disassemble n instructions either side of ip (default 5)
+i, in v...         queue numeric input
+a, ascii text      queue text as ascii input, followed by a newline
+h, help            show this
+q, quit            exit
+An empty line repeats the last command.";
+
+const ROW: usize = 8;
+
+struct Debugger {
+    computer: IntCode,
+    breakpoints: BTreeSet<usize>,
+    /// Watched cells and the value last seen in each
+    watches: BTreeMap<usize, Op>,
+}
+
+enum Flow {
+    Running,
+    Stopped,
+}
+
+impl Debugger {
+    fn new(mut computer: IntCode) -> Debugger {
+        computer.track_memory();
+        Debugger {
+            computer,
+            breakpoints: BTreeSet::new(),
+            watches: BTreeMap::new(),
+        }
+    }
+
+    /// Runs one command line, returning false on quit
+    fn execute<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let args: Vec<&str> = words.collect();
+
+        let result = match command {
+            "s" | "step" => parse_or(args.first(), 1).map(|n| self.step(n, out)),
+            "c" | "continue" => Ok(self.cont(out)),
+            "b" | "break" => match args.first() {
+                Some(addr) => parse(addr).map(|addr| {
+                    self.breakpoints.insert(addr);
+                    Ok(())
+                }),
+                None => Ok(list(out, "breakpoints", self.breakpoints.iter())),
+            },
+            "d" | "delete" => parse_required(args.first()).map(|addr: usize| {
+                if !self.breakpoints.remove(&addr) && self.watches.remove(&addr).is_none() {
+                    writeln!(out, "nothing set at {}", addr)?;
+                }
+                Ok(())
+            }),
+            "w" | "watch" => match args.first() {
+                Some(addr) => parse(addr).map(|addr| {
+                    self.watches.insert(addr, self.computer.memory().get(addr));
+                    Ok(())
+                }),
+                None => Ok(list(out, "watching", self.watches.keys())),
+            },
+            "r" | "regs" => Ok(self.registers(out)),
+            "stats" => Ok(writeln!(out, "{}", self.computer.stats())),
+            "diff" => Ok(self.diff(out)),
+            "m" | "x" => parse_required(args.first()).and_then(|addr| {
+                let len = parse_or(args.get(1), 16)?;
+                Ok(self.dump(addr, len, command == "x", out))
+            }),
+            "l" | "dis" => parse_or(args.first(), 5).map(|n| self.listing(n, out)),
+            "i" | "in" => args
+                .iter()
+                .map(|arg| parse(arg))
+                .collect::<Result<Vec<Op>, String>>()
+                .map(|values| {
+                    values.into_iter().for_each(|v| self.computer.input(v));
+                    Ok(())
+                }),
+            "a" | "ascii" => {
+                let text = line.trim_start()[command.len()..].trim();
+                for c in text.chars().chain(std::iter::once('\n')) {
+                    self.computer.input(c as Op);
+                }
+                Ok(Ok(()))
+            }
+            "h" | "help" => Ok(writeln!(out, "{}", HELP)),
+            "q" | "quit" => return Ok(false),
+            _ => Err(format!("unknown command {}, try help", command)),
+        };
+
+        match result {
+            Ok(io_result) => io_result?,
+            Err(message) => writeln!(out, "{}", message)?,
+        }
+
+        Ok(true)
+    }
+
+    fn step<W: Write>(&mut self, count: usize, out: &mut W) -> io::Result<()> {
+        for _ in 0..count {
+            if let Flow::Stopped = self.execute_one(true, out)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn cont<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        // Always execute the first instruction, so continuing from a
+        // breakpoint doesn't immediately stop on it again
+        if let Flow::Stopped = self.execute_one(false, out)? {
+            return Ok(());
+        }
+
+        loop {
+            let ip = self.computer.ip();
+            if self.breakpoints.contains(&ip) {
+                writeln!(out, "breakpoint at {}", ip)?;
+                return self.listing(0, out);
+            }
+
+            if let Flow::Stopped = self.execute_one(false, out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn execute_one<W: Write>(&mut self, verbose: bool, out: &mut W) -> io::Result<Flow> {
+        let step = match self.computer.step() {
+            Ok(step) => step,
+            Err(error) => {
+                writeln!(out, "fault: {}", error)?;
+                return Ok(Flow::Stopped);
+            }
+        };
+
+        match step.status {
+            Some(Status::NeedsInput) => {
+                writeln!(out, "waiting for input at {}", step.ip)?;
+                return Ok(Flow::Stopped);
+            }
+            Some(Status::Halted) => {
+                writeln!(out, "halted at {}", step.ip)?;
+                return Ok(Flow::Stopped);
+            }
+            _ => {}
+        }
+
+        if verbose {
+            writeln!(out, "{}", step)?;
+        }
+
+        if let Some(Status::Output(value)) = step.status {
+            writeln!(out, "output: {}", value)?;
+        }
+
+        if let Some((address, value)) = step.write {
+            if let Some(seen) = self.watches.get_mut(&address) {
+                writeln!(out, "watch [{}]: {} -> {}", address, seen, value)?;
+                *seen = value;
+                return Ok(Flow::Stopped);
+            }
+        }
+
+        Ok(Flow::Running)
+    }
+
+    fn diff<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if let Some(footprint) = self.computer.footprint() {
+            for write in footprint.code_writes() {
+                writeln!(out, "{}", write)?;
+            }
+        }
+
+        match self.computer.memory_diff() {
+            Some(diff) if !diff.is_empty() => write!(out, "{}", diff),
+            _ => writeln!(out, "memory unchanged"),
+        }
+    }
+
+    fn registers<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "ip {}  rb {}  input {:?}  outputs {}",
+            self.computer.ip(),
+            self.computer.relative_base(),
+            self.computer.pending_input(),
+            self.computer.output().len()
+        )
+    }
+
+    /// Shows `len` cells from `start`, though never more than memory holds
+    fn dump<W: Write>(&self, start: usize, len: usize, hex: bool, out: &mut W) -> io::Result<()> {
+        let memory = self.computer.memory();
+        let end = start.saturating_add(len.min(memory.used()));
+        for row in (start..end).step_by(ROW) {
+            write!(out, "{:>6}:", row)?;
+            for address in row..row.saturating_add(ROW).min(end) {
+                let value = memory.get(address);
+                match (hex, value < 0) {
+                    (true, true) => write!(out, " {:>8}", format!("-{:x}", value.unsigned_abs()))?,
+                    (true, false) => write!(out, " {:>8x}", value)?,
+                    (false, _) => write!(out, " {:>8}", value)?,
+                }
+            }
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+
+    /// Disassembles `count` instructions either side of ip. Going backwards
+    /// is a guess: it sweeps from the earliest cell that lines up with ip.
+    /// There can't be more instructions than memory holds cells.
+    fn listing<W: Write>(&self, count: usize, out: &mut W) -> io::Result<()> {
+        let memory = self.computer.memory();
+        let count = count.min(memory.used());
+        let ip = self.computer.ip();
+        let start = ip.saturating_sub(count * 4);
+        let end = ip.saturating_add((count + 1) * 4);
+        let cells: Vec<Op> = (start..end).map(|address| memory.get(address)).collect();
+
+        // A sweep starting at ip itself always lines up, so this finds one
+        let (from, listing) = (start..=ip)
+            .map(|from| (from, disassemble(&cells[from - start..])))
+            .find(|(from, listing)| listing.iter().any(|&(a, _)| from + a == ip))
+            .unwrap();
+        let at = listing.iter().position(|&(a, _)| from + a == ip).unwrap();
+
+        let shown = at.saturating_sub(count)..(at + count + 1).min(listing.len());
+        for &(address, instruction) in &listing[shown] {
+            let address = from + address;
+            let marker = if address == ip { "=>" } else { "  " };
+            let flag = if self.breakpoints.contains(&address) {
+                '*'
+            } else {
+                ' '
+            };
+            writeln!(out, "{}{}{:>6}: {}", marker, flag, address, instruction)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn list<'a, W: Write, I: Iterator<Item = &'a usize>>(
+    out: &mut W,
+    name: &str,
+    addresses: I,
+) -> io::Result<()> {
+    let addresses: Vec<String> = addresses.map(|a| a.to_string()).collect();
+    writeln!(out, "{}: {}", name, addresses.join(" "))
+}
+
+fn parse<T: std::str::FromStr>(arg: &str) -> Result<T, String> {
+    arg.parse().map_err(|_| format!("bad number {}", arg))
+}
+
+fn parse_or<T: std::str::FromStr>(arg: Option<&&str>, default: T) -> Result<T, String> {
+    arg.map_or(Ok(default), |arg| parse(arg))
+}
+
+fn parse_required<T: std::str::FromStr>(arg: Option<&&str>) -> Result<T, String> {
+    arg.ok_or_else(|| "missing address".to_string())
+        .and_then(|arg| parse(arg))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: intcode-debug <program>");
+            process::exit(2);
+        }
+    };
+
+    let mut debugger = Debugger::new(IntCode::from_path(path)?);
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut last = String::new();
+    writeln!(out, "type help for commands")?;
+    debugger.listing(0, &mut out)?;
+
+    loop {
+        write!(out, "(ic) ")?;
+        out.flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        if line.trim().is_empty() {
+            line = last.clone();
+        }
+
+        if !debugger.execute(&line, &mut out)? {
+            return Ok(());
+        }
+
+        last = line;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(debugger: &mut Debugger, commands: &[&str]) -> String {
+        let mut out = Vec::new();
+        for command in commands {
+            debugger.execute(command, &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    // Counts down from the input, outputting each value
+    const COUNTDOWN: &str = "3,20,4,20,1001,20,-1,20,1005,20,2,99";
+
+    #[test]
+    fn breakpoints() {
+        let mut debugger = Debugger::new(IntCode::new(COUNTDOWN).unwrap());
+        let out = run(&mut debugger, &["c", "in 2", "b 8", "c", "c", "c"]);
+        assert_eq!(
+            out,
+            "waiting for input at 0
+output: 2
+breakpoint at 8
+=>*     8: JT [20], #2
+output: 1
+breakpoint at 8
+=>*     8: JT [20], #2
+halted at 11
+"
+        );
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut debugger = Debugger::new(IntCode::new(COUNTDOWN).unwrap());
+        let out = run(&mut debugger, &["w 20", "i 3", "c", "c", "c", "r"]);
+        assert_eq!(
+            out,
+            "watch [20]: 0 -> 3
+output: 3
+watch [20]: 3 -> 2
+output: 2
+watch [20]: 2 -> 1
+ip 8  rb 0  input []  outputs 2
+"
+        );
+    }
+
+    #[test]
+    fn diff() {
+        let mut debugger = Debugger::new(IntCode::new("1101,1,1,4,0,99").unwrap());
+        assert_eq!(run(&mut debugger, &["diff"]), "memory unchanged\n");
+        let out = run(&mut debugger, &["s", "s", "diff"]);
+        assert!(out.ends_with("4: 0 -> 2\n"), "{}", out);
+    }
+
+    #[test]
+    fn huge_arguments() {
+        let mut debugger = Debugger::new(IntCode::new("-9223372036854775808,99").unwrap());
+        let out = run(&mut debugger, &["x 0 18446744073709551615"]);
+        assert_eq!(out, "     0: -8000000000000000       63\n");
+
+        let out = run(
+            &mut debugger,
+            &["x 18446744073709551614 9", "dis 1000000000000"],
+        );
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            vec![
+                "18446744073709551614:        0",
+                "=>      0: DB -9223372036854775808",
+                "        1: HLT",
+                "        2: DB 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn step_and_dump() {
+        let mut debugger = Debugger::new(IntCode::new("1101,-1,255,5,99").unwrap());
+        let out = run(&mut debugger, &["s 2", "x 0 5", "m 4 2", "bogus"]);
+        assert_eq!(
+            out,
+            "     0: ADD #-1, #255 -> [5]             ; -1 255 [5]=254
+halted at 4
+     0:      44d       -1       ff        5       63
+     4:       99      254
+unknown command bogus, try help
+"
+        );
+    }
+
+    #[test]
+    fn listing_lines_up_with_ip() {
+        let mut debugger = Debugger::new(IntCode::new(COUNTDOWN).unwrap());
+        let out = run(&mut debugger, &["in 5", "s 3", "dis 2"]);
+        let listing: Vec<&str> = out.lines().skip(4).collect();
+        assert_eq!(
+            listing,
+            vec![
+                "        2: OUT [20]",
+                "        4: ADD [20], #-1 -> [20]",
+                "=>      8: JT [20], #2",
+                "       11: HLT",
+                "       12: DB 0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use intcode::{IntCode, Op, Session, Status};
+use std::io::{self, BufRead, Write};
+use std::{env, process};
+
+const USAGE: &str = "\
+usage: intcode [options] <program>
+
+Runs an intcode program, given as text or as a binary image. Inputs
+given with -i are used first, then more are read from stdin as the
+program asks for them.
+
+  -i, --input N[,N...]  queue numeric input, may be repeated
+  -a, --ascii           stdin lines are sent as character codes ending in a
+                        newline, outputs below 256 print as characters
+  -s, --max-steps N     stop after executing N instructions
+      --stats           print execution statistics to stderr at the end
+  -r, --record FILE     save every input and output to a session file
+      --replay FILE     rerun a recorded session instead of reading stdin,
+                        checking the program does exactly the same again,
+                        can't be combined with any other option
+      --diff            print memory changed by the run to stderr at the
+                        end, along with any writes into executed code
+  -h, --help            show this
+
+Exits 0 when the program halts, 1 on a machine fault, 2 on bad usage or an
+unreadable program, 3 when stdin runs out while the program wants input,
+4 when the step limit is reached, and 5 when a replay doesn't match.";
+
+const HALTED: i32 = 0;
+const FAULT: i32 = 1;
+const USAGE_ERROR: i32 = 2;
+const OUT_OF_INPUT: i32 = 3;
+const OUT_OF_STEPS: i32 = 4;
+const MISMATCH: i32 = 5;
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    program: String,
+    inputs: Vec<Op>,
+    ascii: bool,
+    max_steps: Option<u64>,
+    stats: bool,
+    record: Option<String>,
+    replay: Option<String>,
+    diff: bool,
+}
+
+/// `None` when only asked for help
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Options>, String> {
+    let mut program = None;
+    let mut inputs = Vec::new();
+    let mut ascii = false;
+    let mut max_steps = None;
+    let mut stats = false;
+    let mut record = None;
+    let mut replay = None;
+    let mut diff = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-a" | "--ascii" => ascii = true,
+            "-i" | "--input" => {
+                let values = args.next().ok_or("-i needs a value")?;
+                inputs.extend(parse_numbers(&values)?);
+            }
+            "-s" | "--max-steps" => {
+                let steps = args.next().ok_or("-s needs a value")?;
+                let steps = steps
+                    .parse()
+                    .map_err(|_| format!("bad step count {}", steps))?;
+                max_steps = Some(steps);
+            }
+            "--stats" => stats = true,
+            "-r" | "--record" => record = Some(args.next().ok_or("-r needs a file")?),
+            "--replay" => replay = Some(args.next().ok_or("--replay needs a file")?),
+            "--diff" => diff = true,
+            "-h" | "--help" => return Ok(None),
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("unknown option {}\n\n{}", flag, USAGE))
+            }
+            _ if program.is_some() => return Err(format!("unexpected argument {}", arg)),
+            _ => program = Some(arg),
+        }
+    }
+
+    // A replay supplies its own input and checks rather than runs
+    let others = !inputs.is_empty() || ascii || max_steps.is_some() || stats || diff;
+    if replay.is_some() && (others || record.is_some()) {
+        return Err("--replay can't be combined with other options".to_string());
+    }
+
+    Ok(Some(Options {
+        program: program.ok_or_else(|| USAGE.to_string())?,
+        inputs,
+        ascii,
+        max_steps,
+        stats,
+        record,
+        replay,
+        diff,
+    }))
+}
+
+/// Numbers separated by commas and/or whitespace
+fn parse_numbers(text: &str) -> Result<Vec<Op>, String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.parse().map_err(|_| format!("bad input {}", token)))
+        .collect()
+}
+
+/// Turns one line of stdin into input values
+fn line_to_input(line: &str, ascii: bool) -> Result<Vec<Op>, String> {
+    if ascii {
+        let line = line.trim_end_matches(&['\n', '\r'][..]);
+        Ok(line.chars().chain("\n".chars()).map(|c| c as Op).collect())
+    } else {
+        parse_numbers(line)
+    }
+}
+
+fn write_output<W: Write>(out: &mut W, value: Op, ascii: bool) -> io::Result<()> {
+    if ascii && (0..256).contains(&value) {
+        write!(out, "{}", value as u8 as char)
+    } else if ascii {
+        // Keep a number from running into surrounding text
+        writeln!(out, "\n{}", value)
+    } else {
+        writeln!(out, "{}", value)
+    }
+}
+
+fn run(options: &Options) -> io::Result<i32> {
+    let mut computer = match IntCode::from_path(&options.program) {
+        Ok(computer) => computer,
+        Err(error) => {
+            eprintln!("{}: {}", options.program, error);
+            return Ok(USAGE_ERROR);
+        }
+    };
+
+    if let Some(path) = &options.replay {
+        return Ok(replay(&mut computer, path));
+    }
+
+    for &value in options.inputs.iter() {
+        computer.input(value);
+    }
+    computer.set_budget(options.max_steps);
+    if options.record.is_some() {
+        computer.record();
+    }
+    if options.diff {
+        computer.track_memory();
+    }
+
+    let code = interact(&mut computer, options)?;
+    if let (Some(path), Some(session)) = (&options.record, computer.session()) {
+        if let Err(error) = session.save(path) {
+            eprintln!("{}: {}", path, error);
+            return Ok(USAGE_ERROR);
+        }
+    }
+    if options.stats {
+        eprintln!("{}", computer.stats());
+    }
+    if let (Some(footprint), Some(diff)) = (computer.footprint(), computer.memory_diff()) {
+        for write in footprint.code_writes() {
+            eprintln!("{}", write);
+        }
+        eprint!("{}", diff);
+    }
+
+    Ok(code)
+}
+
+fn replay(computer: &mut IntCode, path: &str) -> i32 {
+    let session = match Session::load(path) {
+        Ok(session) => session,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            return USAGE_ERROR;
+        }
+    };
+
+    match session.replay(computer) {
+        Ok(()) => HALTED,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            MISMATCH
+        }
+    }
+}
+
+fn interact(computer: &mut IntCode, options: &Options) -> io::Result<i32> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    loop {
+        match computer.resume() {
+            Ok(Status::Output(value)) => write_output(&mut out, value, options.ascii)?,
+            Ok(Status::NeedsInput) => {
+                // Whatever the program printed is probably a prompt
+                out.flush()?;
+                let line = match lines.next() {
+                    Some(line) => line?,
+                    None => {
+                        eprintln!("ran out of input at ip {}", computer.ip());
+                        return Ok(OUT_OF_INPUT);
+                    }
+                };
+
+                match line_to_input(&line, options.ascii) {
+                    Ok(values) => values.into_iter().for_each(|v| computer.input(v)),
+                    Err(message) => {
+                        eprintln!("{}", message);
+                        return Ok(USAGE_ERROR);
+                    }
+                }
+            }
+            Ok(Status::Halted) => {
+                out.flush()?;
+                return Ok(HALTED);
+            }
+            Ok(Status::BudgetExhausted) => {
+                out.flush()?;
+                eprintln!("step limit reached at ip {}", computer.ip());
+                return Ok(OUT_OF_STEPS);
+            }
+            Err(error) => {
+                out.flush()?;
+                eprintln!("{}", error);
+                return Ok(FAULT);
+            }
+        }
+    }
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            process::exit(HALTED);
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(USAGE_ERROR);
+        }
+    };
+
+    match run(&options) {
+        Ok(code) => process::exit(code),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(USAGE_ERROR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string())).map(Option::unwrap)
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(
+            args(&["-i", "1,2", "--ascii", "day.txt", "--input", "-3", "-s", "50"]),
+            Ok(Options {
+                program: "day.txt".to_string(),
+                inputs: vec![1, 2, -3],
+                ascii: true,
+                max_steps: Some(50),
+                stats: false,
+                record: None,
+                replay: None,
+                diff: false,
+            })
+        );
+        assert!(args(&["--stats", "day.txt"]).unwrap().stats);
+        assert!(args(&["day.txt", "--diff"]).unwrap().diff);
+        assert_eq!(
+            args(&["-r", "game.session", "day.txt"]).unwrap().record,
+            Some("game.session".to_string())
+        );
+        assert!(args(&["day.txt", "--replay"]).is_err());
+        assert!(args(&["--replay", "game.session", "day.txt"]).is_ok());
+        assert!(args(&["--replay", "game.session", "-i", "1", "day.txt"]).is_err());
+        assert!(args(&["--stats", "--replay", "game.session", "day.txt"]).is_err());
+        assert_eq!(parse_args(vec!["--help".to_string()].into_iter()), Ok(None));
+        assert!(args(&["-s", "lots", "day.txt"]).is_err());
+        assert!(args(&[]).is_err());
+        assert!(args(&["--bogus", "day.txt"]).is_err());
+        assert!(args(&["a.txt", "b.txt"]).is_err());
+        assert!(args(&["-i", "x", "a.txt"]).is_err());
+    }
+
+    #[test]
+    fn input_lines() {
+        assert_eq!(line_to_input("1, 2 -3", false), Ok(vec![1, 2, -3]));
+        assert_eq!(line_to_input("A,1\r\n", true), Ok(vec![65, 44, 49, 10]));
+        assert!(line_to_input("1 two", false).is_err());
+    }
+
+    #[test]
+    fn ascii_output() {
+        let mut out = Vec::new();
+        for &value in [72, 105, 10, 11372].iter() {
+            write_output(&mut out, value, true).unwrap();
+        }
+        write_output(&mut out, 65, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Hi\n\n11372\n65\n");
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{Instruction, IntCode, Result, Status};
+
+/// How `IntCode` gets from memory to a decoded instruction
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Engine {
+    /// Decode every instruction every time it runs
+    Reference,
+    /// Decode each address once and reuse it until something writes over
+    /// the instruction. Behaves identically to `Reference`, just faster on
+    /// programs that loop.
+    Cached,
+}
+
+/// Longest instruction, so the furthest back a write can land in one
+const MAX_SIZE: usize = 4;
+
+/// Instructions at or above this address aren't cached. Code that high up
+/// lives in sparse memory, and a table reaching it would be enormous.
+const MAX_CACHED: usize = 1 << 20;
+
+/// Decoded instructions by address
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DecodeCache {
+    entries: Vec<Option<Instruction>>,
+    /// `Memory::generation` the entries were decoded against
+    generation: u64,
+}
+
+impl DecodeCache {
+    /// Drops everything if memory was poked from outside since the last
+    /// lookup, since those writes don't go through `invalidate`
+    pub(crate) fn sync(&mut self, generation: u64) {
+        if self.generation != generation {
+            self.clear();
+            self.generation = generation;
+        }
+    }
+
+    pub(crate) fn get(&self, ip: usize) -> Option<Instruction> {
+        self.entries.get(ip).copied().flatten()
+    }
+
+    pub(crate) fn insert(&mut self, ip: usize, instruction: Instruction) {
+        if ip >= MAX_CACHED {
+            return;
+        }
+
+        if ip >= self.entries.len() {
+            self.entries.resize(ip + 1, None);
+        }
+
+        self.entries[ip] = Some(instruction);
+    }
+
+    /// Forgets any instruction overlapping `address`
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        let start = address.saturating_sub(MAX_SIZE - 1);
+        let end = (address + 1).min(self.entries.len());
+        for entry in self.entries.iter_mut().take(end).skip(start) {
+            *entry = None;
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl IntCode {
+    /// `resume` for the cached engine when there's no tracer to feed. Skips
+    /// building a `Step` for every instruction, otherwise this must behave
+    /// exactly like `IntCode::execute`.
+    pub(crate) fn resume_cached(&mut self) -> Result<Status> {
+        // The host can't poke memory until this returns, and our own
+        // writes invalidate as they go
+        self.cache.sync(self.ops.generation());
+        loop {
+            let instruction = match self.cache.get(self.ip) {
+                Some(instruction) => instruction,
+                None => {
+                    let instruction = self.decode()?;
+                    self.cache.insert(self.ip, instruction);
+                    instruction
+                }
+            };
+
+            if self.budget == Some(0) && instruction != Instruction::Halt {
+                return Ok(Status::BudgetExhausted);
+            }
+
+            if let Some(footprint) = self.footprint.as_mut() {
+                footprint.executing(self.ip, instruction.size());
+            }
+
+            let mut next_ip = self.ip + instruction.size();
+            match instruction {
+                Instruction::Add(a, b, c) => {
+                    let value = self.add(self.read(a)?, self.read(b)?)?;
+                    self.write(c, value)?;
+                }
+                Instruction::Multiply(a, b, c) => {
+                    let value = self.multiply(self.read(a)?, self.read(b)?)?;
+                    self.write(c, value)?;
+                }
+                Instruction::Input(a) => {
+                    let value = match self.next_input() {
+                        Some(value) => value,
+                        None => return Ok(Status::NeedsInput),
+                    };
+
+                    if let Err(error) = self.write(a, value) {
+                        self.unread(value);
+                        return Err(error);
+                    }
+
+                    if self.input_pos < self.input.len() {
+                        self.input_pos += 1;
+                    }
+                    self.stats.inputs += 1;
+                    if let Some(session) = self.session.as_mut() {
+                        session.input(self.stats.steps, value);
+                    }
+                }
+                Instruction::Output(a) => {
+                    let out = self.read(a)?;
+                    self.stats.outputs += 1;
+                    if let Some(session) = self.session.as_mut() {
+                        session.output(self.stats.steps, out);
+                    }
+                    match self.output_port.as_mut() {
+                        Some(port) => port.write(out),
+                        None => self.output.push(out),
+                    }
+                    self.retire(&instruction);
+                    self.ip = next_ip;
+                    return Ok(Status::Output(out));
+                }
+                Instruction::JumpIfTrue(a, b) => {
+                    if self.read(a)? != 0 {
+                        next_ip = self.target(b)?;
+                        self.stats.jumps += 1;
+                    }
+                }
+                Instruction::JumpIfFalse(a, b) => {
+                    if self.read(a)? == 0 {
+                        next_ip = self.target(b)?;
+                        self.stats.jumps += 1;
+                    }
+                }
+                Instruction::LessThan(a, b, c) => {
+                    let value = if self.read(a)? < self.read(b)? { 1 } else { 0 };
+                    self.write(c, value)?;
+                }
+                Instruction::Equals(a, b, c) => {
+                    let value = if self.read(a)? == self.read(b)? { 1 } else { 0 };
+                    self.write(c, value)?;
+                }
+                Instruction::AdjustRelativeBase(a) => {
+                    self.relative_base = self.add(self.relative_base, self.read(a)?)?;
+                }
+                Instruction::Halt => {
+                    if let Some(footprint) = self.footprint.as_mut() {
+                        footprint.ran();
+                    }
+                    return Ok(Status::Halted);
+                }
+                Instruction::Data(_) => unreachable!("decode never yields data"),
+            }
+
+            self.retire(&instruction);
+            self.ip = next_ip;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Param;
+
+    #[test]
+    fn invalidate_overlapping() {
+        let mut cache = DecodeCache::default();
+        let add = Instruction::Add(Param::Position(0), Param::Position(0), Param::Position(0));
+        cache.insert(0, add);
+        cache.insert(4, Instruction::Halt);
+
+        cache.invalidate(3);
+        assert_eq!(cache.get(0), None);
+        assert_eq!(cache.get(4), Some(Instruction::Halt));
+
+        cache.sync(1);
+        assert_eq!(cache.get(4), None);
+    }
+
+    #[test]
+    fn code_in_sparse_memory() {
+        // Writes an output instruction far above the image and jumps to it
+        let mut computer = IntCode::new("1101,4,0,1099511627776,1105,1,1099511627776").unwrap();
+        computer.set_engine(Engine::Cached);
+        assert_eq!(computer.resume(), Ok(Status::Output(1101)));
+        assert_eq!(
+            computer.resume(),
+            Err(crate::VmError::UnknownOpcode {
+                ip: (1 << 40) + 2,
+                opcode: 0,
+                operand: Some(0)
+            })
+        );
+    }
+}
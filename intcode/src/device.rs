@@ -0,0 +1,140 @@
+use crate::{IntCode, Op, Status, VmError};
+
+/// A peripheral a machine talks to through its I/O. Output is delivered in
+/// frames of `arity` values, such as an x, y, tile triple, and input is
+/// asked for only when the program blocks on it.
+pub trait Device {
+    /// Returned by the device's own callbacks. Machine faults are converted
+    /// into it, so a device and the program driving it fail the same way.
+    type Error: From<VmError>;
+
+    /// Number of output values that make up one frame
+    fn arity(&self) -> usize;
+
+    /// Handles one complete frame of output
+    fn output(&mut self, frame: &[Op]) -> Result<(), Self::Error>;
+
+    /// Supplies the value the program is waiting for, or `None` to stop
+    /// running and hand the machine back to the caller
+    fn input(&mut self) -> Result<Option<Op>, Self::Error>;
+}
+
+impl IntCode {
+    /// Runs with `device` attached until the program halts, the device
+    /// declines to give input, or the budget runs out. The returned status
+    /// says which. A frame cut short by a halt is dropped, one cut short
+    /// any other way is finished on the next call.
+    pub fn run_device<D: Device>(&mut self, device: &mut D) -> Result<Status, D::Error> {
+        let arity = device.arity();
+        loop {
+            match self.resume()? {
+                Status::Output(value) => {
+                    self.frame.push(value);
+                    if self.frame.len() == arity {
+                        let frame = std::mem::take(&mut self.frame);
+                        device.output(&frame)?;
+                    }
+                }
+                Status::NeedsInput => match device.input()? {
+                    Some(value) => self.input(value),
+                    None => return Ok(Status::NeedsInput),
+                },
+                Status::Halted => {
+                    self.frame.clear();
+                    return Ok(Status::Halted);
+                }
+                Status::BudgetExhausted => return Ok(Status::BudgetExhausted),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads pairs until it's given a zero, outputting each pair's sum and
+    // product
+    const SUMS: &str =
+        "3,100,1006,100,22,3,101,1,100,101,102,2,100,101,103,4,102,4,103,1105,1,0,99";
+
+    /// Hands out its queue, then declines
+    #[derive(Default)]
+    struct Recorder {
+        queue: Vec<Op>,
+        frames: Vec<Vec<Op>>,
+    }
+
+    impl Device for Recorder {
+        type Error = VmError;
+
+        fn arity(&self) -> usize {
+            2
+        }
+
+        fn output(&mut self, frame: &[Op]) -> Result<(), VmError> {
+            self.frames.push(frame.to_vec());
+            Ok(())
+        }
+
+        fn input(&mut self) -> Result<Option<Op>, VmError> {
+            Ok(if self.queue.is_empty() {
+                None
+            } else {
+                Some(self.queue.remove(0))
+            })
+        }
+    }
+
+    #[test]
+    fn frames_and_input() {
+        let mut computer = IntCode::new(SUMS).unwrap();
+        let mut device = Recorder {
+            queue: vec![2, 3, 4],
+            ..Recorder::default()
+        };
+        assert_eq!(computer.run_device(&mut device), Ok(Status::NeedsInput));
+        assert_eq!(device.frames, vec![vec![5, 6]]);
+
+        // Picks up again where the device left off
+        device.queue = vec![5, 0];
+        assert_eq!(computer.run_device(&mut device), Ok(Status::Halted));
+        assert_eq!(device.frames, vec![vec![5, 6], vec![9, 20]]);
+    }
+
+    #[test]
+    fn budget_splits_frame() {
+        let mut computer = IntCode::new(SUMS).unwrap();
+        let mut device = Recorder {
+            queue: vec![2, 3, 0],
+            ..Recorder::default()
+        };
+        computer.set_budget(Some(6));
+        assert_eq!(
+            computer.run_device(&mut device),
+            Ok(Status::BudgetExhausted)
+        );
+        assert!(device.frames.is_empty());
+
+        computer.set_budget(None);
+        assert_eq!(computer.run_device(&mut device), Ok(Status::Halted));
+        assert_eq!(device.frames, vec![vec![5, 6]]);
+    }
+
+    #[test]
+    fn faults_pass_through() {
+        let mut computer = IntCode::new("3,0,42").unwrap();
+        let mut device = Recorder {
+            queue: vec![3],
+            ..Recorder::default()
+        };
+        assert_eq!(
+            computer.run_device(&mut device),
+            Err(VmError::UnknownOpcode {
+                ip: 2,
+                opcode: 42,
+                operand: Some(0)
+            })
+        );
+    }
+}
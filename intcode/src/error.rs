@@ -0,0 +1,147 @@
+use crate::Op;
+use std::error::Error;
+use std::{fmt, io};
+
+/// Everything that can go wrong loading or running a program. Runtime
+/// variants carry the address of the faulting instruction and its raw
+/// opcode so a bad program can be tracked down without a debugger.
+#[derive(PartialEq, Debug, Clone)]
+pub enum VmError {
+    /// Token `index` (0 based) of the program text isn't an integer
+    Parse { index: usize, token: String },
+    /// `operand` is the cell after the opcode, `None` past the end of the
+    /// address space
+    UnknownOpcode {
+        ip: usize,
+        opcode: Op,
+        operand: Option<Op>,
+    },
+    /// An opcode too big for an `Op`, in decimal. Only `BigIntCode` can
+    /// hold one.
+    BigOpcode { ip: usize, opcode: String },
+    /// Parameter mode digit isn't 0, 1 or 2, for the parameter `operand`
+    InvalidMode {
+        ip: usize,
+        opcode: Op,
+        operand: Op,
+        mode: Op,
+    },
+    /// Instruction tried to store its result into an immediate operand
+    ImmediateWrite { ip: usize, opcode: Op, operand: Op },
+    NegativeAddress {
+        ip: usize,
+        opcode: Op,
+        operand: Op,
+        address: Op,
+    },
+    /// Writing `address` would allocate more than `limit` cells
+    MemoryLimit {
+        ip: usize,
+        opcode: Op,
+        operand: Op,
+        address: usize,
+        limit: usize,
+    },
+    /// Arithmetic on `a` and `b` overflowed under `Overflow::Checked`
+    Overflow { ip: usize, opcode: Op, a: Op, b: Op },
+    /// The instruction at `ip` runs past the end of the address space
+    IpOverflow { ip: usize, opcode: Op },
+    /// Saved state or binary image can't be decoded, `offset` is in bytes
+    Corrupt { offset: usize, reason: &'static str },
+    /// Reading a program failed. Keeps the message rather than the
+    /// `io::Error` so errors stay comparable and cloneable.
+    Io {
+        kind: io::ErrorKind,
+        message: String,
+    },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::Parse { index, token } => {
+                write!(f, "token {} ({:?}) is not an integer", index, token)
+            }
+            VmError::UnknownOpcode {
+                ip,
+                opcode,
+                operand: Some(operand),
+            } => write!(
+                f,
+                "unknown opcode {} at ip {} (operand {})",
+                opcode, ip, operand
+            ),
+            VmError::UnknownOpcode {
+                ip,
+                opcode,
+                operand: None,
+            } => write!(f, "unknown opcode {} at ip {}", opcode, ip),
+            VmError::BigOpcode { ip, opcode } => {
+                write!(f, "unknown opcode {} at ip {}", opcode, ip)
+            }
+            VmError::InvalidMode {
+                ip,
+                opcode,
+                operand,
+                mode,
+            } => write!(
+                f,
+                "invalid parameter mode {} in opcode {} at ip {} (operand {})",
+                mode, opcode, ip, operand
+            ),
+            VmError::ImmediateWrite {
+                ip,
+                opcode,
+                operand,
+            } => write!(
+                f,
+                "opcode {} at ip {} writes to immediate operand {}",
+                opcode, ip, operand
+            ),
+            VmError::NegativeAddress {
+                ip,
+                opcode,
+                operand,
+                address,
+            } => write!(
+                f,
+                "opcode {} at ip {} addresses negative cell {} (operand {})",
+                opcode, ip, address, operand
+            ),
+            VmError::MemoryLimit {
+                ip,
+                opcode,
+                operand,
+                address,
+                limit,
+            } => write!(
+                f,
+                "opcode {} at ip {} writes cell {} (operand {}) past the {} cell memory limit",
+                opcode, ip, address, operand, limit
+            ),
+            VmError::Overflow { ip, opcode, a, b } => write!(
+                f,
+                "opcode {} at ip {} overflowed on operands {} and {}",
+                opcode, ip, a, b
+            ),
+            VmError::IpOverflow { ip, opcode } => write!(
+                f,
+                "opcode {} at ip {} runs past the end of memory",
+                opcode, ip
+            ),
+            VmError::Corrupt { offset, reason } => write!(f, "{} at byte {}", reason, offset),
+            VmError::Io { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for VmError {}
+
+impl From<io::Error> for VmError {
+    fn from(error: io::Error) -> VmError {
+        VmError::Io {
+            kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+}
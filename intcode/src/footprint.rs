@@ -0,0 +1,287 @@
+use crate::{IntCode, Memory, Op, Ops};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Range;
+
+/// A write into a cell that had already been run as part of an instruction
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct CodeWrite {
+    /// The instruction doing the writing
+    pub ip: usize,
+    pub address: usize,
+    pub value: Op,
+}
+
+impl fmt::Display for CodeWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ip {} wrote {} into code at {}",
+            self.ip, self.value, self.address
+        )
+    }
+}
+
+/// Which cells a machine has written and executed since
+/// `IntCode::track_memory` was called, and what it was writing into code
+#[derive(PartialEq, Debug, Clone)]
+pub struct Footprint {
+    /// Memory when tracking started
+    baseline: Memory,
+    written: BTreeSet<usize>,
+    /// Every cell of every instruction that ran to completion
+    executed: BTreeSet<usize>,
+    /// Cells of the instruction underway, which count as code for its own
+    /// writes but only join `executed` once it finishes
+    running: Range<usize>,
+    code_writes: Vec<CodeWrite>,
+}
+
+impl Footprint {
+    pub fn baseline(&self) -> &Memory {
+        &self.baseline
+    }
+
+    /// Addresses written by the program, in order. Cells set by the host,
+    /// such as with `IntCode::patch` or a `Program`'s patches, aren't
+    /// counted.
+    pub fn written(&self) -> &BTreeSet<usize> {
+        &self.written
+    }
+
+    pub fn executed(&self) -> &BTreeSet<usize> {
+        &self.executed
+    }
+
+    /// Self modification, in the order it happened
+    pub fn code_writes(&self) -> &[CodeWrite] {
+        &self.code_writes
+    }
+
+    pub(crate) fn executing(&mut self, ip: usize, size: usize) {
+        self.running = ip..ip + size;
+    }
+
+    /// The instruction from the last `executing` finished
+    pub(crate) fn ran(&mut self) {
+        self.executed.extend(self.running.clone());
+    }
+
+    pub(crate) fn wrote(&mut self, ip: usize, address: usize, value: Op) {
+        self.written.insert(address);
+        if self.executed.contains(&address) || self.running.contains(&address) {
+            self.code_writes.push(CodeWrite { ip, address, value });
+        }
+    }
+}
+
+/// Contiguous cells that differ between two memories
+#[derive(PartialEq, Debug, Clone)]
+pub struct Change {
+    pub start: usize,
+    pub before: Ops,
+    pub after: Ops,
+}
+
+impl Change {
+    /// One past the last changed cell
+    pub fn end(&self) -> usize {
+        self.start + self.before.len()
+    }
+}
+
+/// `start..end: before -> after`, with the values comma separated
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |ops: &[Op]| {
+            let tokens: Vec<String> = ops.iter().map(Op::to_string).collect();
+            tokens.join(",")
+        };
+
+        if self.before.len() == 1 {
+            write!(f, "{}: ", self.start)?;
+        } else {
+            write!(f, "{}..{}: ", self.start, self.end())?;
+        }
+        write!(f, "{} -> {}", join(&self.before), join(&self.after))
+    }
+}
+
+/// Every cell that differs between two memories, grouped into runs of
+/// neighbouring addresses. Cells that were never allocated count as 0.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct MemoryDiff {
+    pub changes: Vec<Change>,
+}
+
+impl MemoryDiff {
+    pub fn between(before: &Memory, after: &Memory) -> MemoryDiff {
+        let sparse = |memory: &Memory| {
+            let cells = memory.sparse_cells().into_iter();
+            cells.map(|(address, _)| address).collect::<Vec<_>>()
+        };
+        let mut addresses: BTreeSet<usize> = (0..before.image_len().max(after.image_len()))
+            .chain(sparse(before))
+            .chain(sparse(after))
+            .collect();
+        addresses.retain(|&address| before.get(address) != after.get(address));
+
+        let mut changes: Vec<Change> = Vec::new();
+        for address in addresses {
+            let (old, new) = (before.get(address), after.get(address));
+            match changes.last_mut() {
+                Some(change) if change.end() == address => {
+                    change.before.push(old);
+                    change.after.push(new);
+                }
+                _ => changes.push(Change {
+                    start: address,
+                    before: vec![old],
+                    after: vec![new],
+                }),
+            }
+        }
+
+        MemoryDiff { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Number of cells that differ
+    pub fn cells(&self) -> usize {
+        self.changes.iter().map(|change| change.before.len()).sum()
+    }
+}
+
+/// One change per line
+impl fmt::Display for MemoryDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in self.changes.iter() {
+            writeln!(f, "{}", change)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl IntCode {
+    /// Starts tracking written and executed cells, taking current memory
+    /// as the baseline for `memory_diff`. Replaces any tracking already
+    /// going on.
+    pub fn track_memory(&mut self) {
+        self.footprint = Some(Footprint {
+            baseline: self.ops.clone(),
+            written: BTreeSet::new(),
+            executed: BTreeSet::new(),
+            running: 0..0,
+            code_writes: Vec::new(),
+        });
+    }
+
+    pub fn footprint(&self) -> Option<&Footprint> {
+        self.footprint.as_ref()
+    }
+
+    pub fn take_footprint(&mut self) -> Option<Footprint> {
+        self.footprint.take()
+    }
+
+    /// How memory differs from when `track_memory` was called, including
+    /// anything the host poked in since
+    pub fn memory_diff(&self) -> Option<MemoryDiff> {
+        self.footprint
+            .as_ref()
+            .map(|footprint| MemoryDiff::between(&footprint.baseline, &self.ops))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, Status};
+
+    // Counts down from 3, but after the first output overwrites its own
+    // output instruction with a halt
+    const PATCHER: &str = "1001,20,-1,20,4,20,1101,0,99,4,1005,20,0,99,0,0,0,0,0,0,3";
+
+    #[test]
+    fn finds_code_writes() {
+        for &engine in [Engine::Reference, Engine::Cached].iter() {
+            let mut computer = IntCode::new(PATCHER).unwrap();
+            computer.set_engine(engine);
+            computer.track_memory();
+            assert_eq!(computer.run(), Ok(Status::Halted));
+            assert_eq!(computer.output(), &vec![2]);
+
+            let footprint = computer.footprint().unwrap();
+            assert_eq!(
+                footprint.written().iter().copied().collect::<Vec<_>>(),
+                vec![4, 20]
+            );
+            assert!(footprint.executed().contains(&12));
+            assert!(!footprint.executed().contains(&13));
+            assert_eq!(
+                footprint.code_writes(),
+                &[CodeWrite {
+                    ip: 6,
+                    address: 4,
+                    value: 99
+                }][..]
+            );
+        }
+    }
+
+    #[test]
+    fn blocked_input_not_executed() {
+        for &engine in [Engine::Reference, Engine::Cached].iter() {
+            let mut computer = IntCode::new("3,0,99").unwrap();
+            computer.set_engine(engine);
+            computer.track_memory();
+            assert_eq!(computer.run(), Ok(Status::NeedsInput));
+            assert!(computer.footprint().unwrap().executed().is_empty());
+
+            computer.input(1);
+            assert_eq!(computer.run(), Ok(Status::Halted));
+            let footprint = computer.footprint().unwrap();
+            assert_eq!(
+                footprint.executed().iter().copied().collect::<Vec<_>>(),
+                vec![0, 1, 2]
+            );
+            // Overwriting its own opcode still counts as a code write
+            assert_eq!(footprint.code_writes().len(), 1);
+        }
+    }
+
+    #[test]
+    fn diff_groups_ranges() {
+        let before = Memory::new(vec![1, 2, 3, 4, 5]);
+        let mut after = before.clone();
+        after[1] = 20;
+        after[2] = 30;
+        after[4] = 50;
+        after.set(9000, 7).unwrap();
+
+        let diff = MemoryDiff::between(&before, &after);
+        assert_eq!(diff.changes.len(), 3);
+        assert_eq!(diff.cells(), 4);
+        assert_eq!(
+            diff.to_string(),
+            "1..3: 2,3 -> 20,30\n4: 5 -> 50\n9000: 0 -> 7\n"
+        );
+        assert!(MemoryDiff::between(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn diff_includes_host_pokes() {
+        let mut computer = IntCode::new("1,0,0,3,99").unwrap();
+        computer.track_memory();
+        computer.ops[1] = 4;
+        computer.run().unwrap();
+        assert_eq!(
+            computer.memory_diff().unwrap().to_string(),
+            "1: 0 -> 4\n3: 3 -> 100\n"
+        );
+    }
+}
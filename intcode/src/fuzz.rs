@@ -0,0 +1,486 @@
+//! Property tests over randomly generated programs. Every case is built
+//! from a seed that's printed on failure, so a bad program can be pulled
+//! out and turned into an ordinary test. Set `INTCODE_FUZZ_CASES` to run
+//! more cases than the default.
+
+use crate::{Engine, Instruction, IntCode, Op, Ops, Result, RingTracer, Status, VmError};
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+
+const DEFAULT_CASES: u64 = 500;
+
+/// Steps a generated program gets, enough to run loops a few times
+const BUDGET: u64 = 400;
+
+/// SplitMix64, plenty for generating test programs
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Uniform in `low..=high`
+    fn range(&mut self, low: Op, high: Op) -> Op {
+        low + self.below((high - low) as u64 + 1) as Op
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<T: Copy>(&mut self, choices: &[T]) -> T {
+        choices[self.below(choices.len() as u64) as usize]
+    }
+}
+
+/// A program that's mostly well formed instructions, with the occasional
+/// invalid opcode, invalid mode, wild operand or bare data cell thrown in
+fn program(rng: &mut Rng) -> Ops {
+    let len = rng.range(4, 48);
+    let mut ops = Vec::new();
+    while (ops.len() as Op) < len {
+        if rng.chance(3) {
+            ops.push(operand(rng, len));
+            continue;
+        }
+
+        let (opcode, count, writes) = if rng.chance(2) {
+            (rng.range(-120, 120), rng.below(4), 0)
+        } else {
+            rng.pick(&[
+                (1, 3, 3),
+                (2, 3, 3),
+                (3, 1, 1),
+                (4, 1, 0),
+                (5, 2, 0),
+                (6, 2, 0),
+                (7, 3, 3),
+                (8, 3, 3),
+                (9, 1, 0),
+                (99, 0, 0),
+            ])
+        };
+        if opcode == 99 && rng.chance(70) {
+            continue;
+        }
+
+        let mut modes = 0;
+        for n in 1..=3 {
+            let mode = match rng.below(100) {
+                0..=1 => rng.range(3, 9),
+                // Writing to an immediate is a fault, keep it rare
+                _ if n == writes && rng.chance(95) => rng.pick(&[0, 2]),
+                _ => rng.range(0, 2),
+            };
+            modes += mode * 10i64.pow(n as u32 + 1);
+        }
+        ops.push(if opcode < 0 {
+            opcode - modes
+        } else {
+            opcode + modes
+        });
+
+        for _ in 0..count {
+            ops.push(operand(rng, len));
+        }
+    }
+
+    ops
+}
+
+/// Mostly addresses inside the program, so reads, writes and jumps land
+/// somewhere interesting
+fn operand(rng: &mut Rng, len: Op) -> Op {
+    match rng.below(20) {
+        0 => rng.pick(&[Op::MIN, Op::MAX, 1 << 40, -(1 << 40)]),
+        1 => rng.range(-3, -1),
+        2 => rng.range(len, len + 2000),
+        _ => rng.range(0, len),
+    }
+}
+
+/// Runs `check` against `cases` generated programs and inputs, reporting
+/// the seed and program of any case that panics
+fn for_programs<F: Fn(&[Op], &[Op], &mut Rng)>(seed: u64, check: F) {
+    let cases = std::env::var("INTCODE_FUZZ_CASES")
+        .ok()
+        .and_then(|cases| cases.parse().ok())
+        .unwrap_or(DEFAULT_CASES);
+
+    for case in 0..cases {
+        let case_seed = seed ^ case.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        let mut rng = Rng(case_seed);
+        let ops = program(&mut rng);
+        let input: Ops = (0..rng.below(5)).map(|_| rng.range(-9, 99)).collect();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| check(&ops, &input, &mut rng)));
+        if result.is_err() {
+            panic!(
+                "case {} (seed {:#x}) failed\nprogram: {}\ninput: {:?}",
+                case,
+                case_seed,
+                crate::to_text(&ops),
+                input
+            );
+        }
+    }
+}
+
+/// Deliberately naive interpreter written straight from the puzzle text,
+/// with the same fault rules as `IntCode` but nothing shared with it
+struct Reference {
+    memory: HashMap<usize, Op>,
+    ip: usize,
+    relative_base: Op,
+    input: VecDeque<Op>,
+    output: Ops,
+    steps: u64,
+}
+
+type Operand = (Op, Op);
+
+impl Reference {
+    fn new(program: &[Op], input: &[Op]) -> Reference {
+        Reference {
+            memory: program.iter().copied().enumerate().collect(),
+            ip: 0,
+            relative_base: 0,
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    fn get(&self, address: usize) -> Op {
+        self.memory.get(&address).copied().unwrap_or(0)
+    }
+
+    /// The opcode and each operand with its mode
+    fn decode(&self) -> Result<(Op, Vec<Operand>)> {
+        let coded = self.get(self.ip);
+        let count = match coded % 100 {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => {
+                return Err(VmError::UnknownOpcode {
+                    ip: self.ip,
+                    opcode: coded,
+                    operand: self.ip.checked_add(1).map(|address| self.get(address)),
+                })
+            }
+        };
+
+        let mut operands = Vec::new();
+        for n in 1..=count {
+            let mode = coded / 10i64.pow(n as u32 + 1) % 10;
+            let operand = self.get(self.ip + n);
+            if !(0..=2).contains(&mode) {
+                return Err(VmError::InvalidMode {
+                    ip: self.ip,
+                    opcode: coded,
+                    operand,
+                    mode,
+                });
+            }
+            operands.push((mode, operand));
+        }
+
+        Ok((coded % 100, operands))
+    }
+
+    fn address(&self, (mode, operand): Operand) -> Result<usize> {
+        let opcode = self.get(self.ip);
+        let address = match mode {
+            0 => operand,
+            2 => self
+                .relative_base
+                .checked_add(operand)
+                .ok_or_else(|| self.overflow(self.relative_base, operand))?,
+            _ => {
+                return Err(VmError::ImmediateWrite {
+                    ip: self.ip,
+                    opcode,
+                    operand,
+                })
+            }
+        };
+
+        if address < 0 {
+            return Err(VmError::NegativeAddress {
+                ip: self.ip,
+                opcode,
+                operand,
+                address,
+            });
+        }
+
+        Ok(address as usize)
+    }
+
+    fn load(&self, operand: Operand) -> Result<Op> {
+        match operand {
+            (1, value) => Ok(value),
+            _ => Ok(self.get(self.address(operand)?)),
+        }
+    }
+
+    fn store(&mut self, operand: Operand, value: Op) -> Result<()> {
+        let address = self.address(operand)?;
+        self.memory.insert(address, value);
+        Ok(())
+    }
+
+    fn target(&self, operand: Operand) -> Result<usize> {
+        let target = self.load(operand)?;
+        if target < 0 {
+            return Err(VmError::NegativeAddress {
+                ip: self.ip,
+                opcode: self.get(self.ip),
+                operand: target,
+                address: target,
+            });
+        }
+
+        Ok(target as usize)
+    }
+
+    fn overflow(&self, a: Op, b: Op) -> VmError {
+        VmError::Overflow {
+            ip: self.ip,
+            opcode: self.get(self.ip),
+            a,
+            b,
+        }
+    }
+
+    /// Runs until anything other than output, executing at most `budget`
+    /// instructions in total
+    fn run(&mut self, budget: u64) -> Result<Status> {
+        loop {
+            let (opcode, p) = self.decode()?;
+            if opcode == 99 {
+                return Ok(Status::Halted);
+            }
+            if self.steps == budget {
+                return Ok(Status::BudgetExhausted);
+            }
+            if opcode == 3 && self.input.is_empty() {
+                return Ok(Status::NeedsInput);
+            }
+
+            let mut next_ip = self.ip + 1 + p.len();
+            match opcode {
+                1 | 2 => {
+                    let (a, b) = (self.load(p[0])?, self.load(p[1])?);
+                    let value = if opcode == 1 {
+                        a.checked_add(b)
+                    } else {
+                        a.checked_mul(b)
+                    };
+                    self.store(p[2], value.ok_or_else(|| self.overflow(a, b))?)?;
+                }
+                3 => {
+                    self.store(p[0], self.input[0])?;
+                    self.input.pop_front();
+                }
+                4 => {
+                    let value = self.load(p[0])?;
+                    self.output.push(value);
+                }
+                5 | 6 => {
+                    if (self.load(p[0])? != 0) == (opcode == 5) {
+                        next_ip = self.target(p[1])?;
+                    }
+                }
+                7 | 8 => {
+                    let (a, b) = (self.load(p[0])?, self.load(p[1])?);
+                    let holds = if opcode == 7 { a < b } else { a == b };
+                    self.store(p[2], holds as Op)?;
+                }
+                _ => {
+                    let value = self.load(p[0])?;
+                    self.relative_base = self
+                        .relative_base
+                        .checked_add(value)
+                        .ok_or_else(|| self.overflow(self.relative_base, value))?;
+                }
+            }
+
+            self.steps += 1;
+            self.ip = next_ip;
+        }
+    }
+}
+
+/// How a run ended and the state it left behind
+#[derive(PartialEq, Debug)]
+struct Outcome {
+    end: Result<Status>,
+    output: Ops,
+    ip: usize,
+    relative_base: Op,
+    steps: u64,
+}
+
+/// Runs until anything but output, like `Reference::run`
+fn run(computer: &mut IntCode) -> Result<Status> {
+    loop {
+        match computer.resume() {
+            Ok(Status::Output(_)) => {}
+            end => return end,
+        }
+    }
+}
+
+fn machine(ops: &[Op], input: &[Op], engine: Engine) -> IntCode {
+    let mut computer = IntCode::init(ops.to_vec());
+    computer.set_engine(engine);
+    for &value in input {
+        computer.input(value);
+    }
+
+    computer
+}
+
+fn outcome(computer: &IntCode, end: Result<Status>) -> Outcome {
+    Outcome {
+        end,
+        output: computer.output().clone(),
+        ip: computer.ip(),
+        relative_base: computer.relative_base(),
+        steps: computer.stats().steps,
+    }
+}
+
+/// Every cell either side has touched reads the same
+fn same_memory(computer: &IntCode, reference: &Reference) -> bool {
+    let image = 0..computer.ops.image_len();
+    let sparse = computer
+        .ops
+        .sparse_cells()
+        .into_iter()
+        .map(|(address, _)| address);
+    let touched = reference.memory.keys().copied();
+    image
+        .chain(sparse)
+        .chain(touched)
+        .all(|address| computer.ops.get(address) == reference.get(address))
+}
+
+#[test]
+fn decodes_every_opcode() {
+    // Modes on operands an instruction doesn't have are ignored, so this
+    // covers every combination that matters
+    for coded in -100_000..=100_000 {
+        let ops = [coded, 5, 6, 7];
+        let read = |address: usize| ops.get(address).copied().unwrap_or(0);
+        let reference = Reference::new(&ops, &[]);
+        match (Instruction::decode(0, read), reference.decode()) {
+            (Ok(instruction), Ok((_, operands))) => {
+                assert_eq!(instruction.params().len(), operands.len(), "{}", coded)
+            }
+            (Err(error), Err(expected)) => assert_eq!(error, expected, "{}", coded),
+            (decoded, expected) => panic!("{}: {:?} vs {:?}", coded, decoded, expected),
+        }
+    }
+}
+
+#[test]
+fn never_panics() {
+    for_programs(0x1ce_c0de, |ops, input, _| {
+        for &engine in [Engine::Reference, Engine::Cached].iter() {
+            let mut computer = machine(ops, input, engine);
+            computer.set_budget(Some(BUDGET));
+            let _ = run(&mut computer);
+
+            // Tracing goes through `step` rather than the fast path
+            let mut traced = machine(ops, input, engine);
+            traced.set_budget(Some(BUDGET));
+            traced.set_tracer(RingTracer::new(4));
+            let _ = run(&mut traced);
+        }
+    });
+}
+
+#[test]
+fn respects_budget() {
+    for_programs(0xb0d6e7, |ops, input, rng| {
+        let budget = rng.below(BUDGET);
+        for &engine in [Engine::Reference, Engine::Cached].iter() {
+            let mut computer = machine(ops, input, engine);
+            computer.set_budget(Some(budget));
+            let end = run(&mut computer);
+            let steps = computer.stats().steps;
+            assert!(steps <= budget);
+            assert_eq!(computer.budget(), Some(budget - steps));
+            if end == Ok(Status::BudgetExhausted) {
+                assert_eq!(steps, budget);
+            }
+        }
+    });
+}
+
+#[test]
+fn budget_slices_resume() {
+    // Running in small slices ends up exactly where one long run does
+    for_programs(0x5_11ce, |ops, input, rng| {
+        for &engine in [Engine::Reference, Engine::Cached].iter() {
+            let mut whole = machine(ops, input, engine);
+            whole.set_budget(Some(BUDGET));
+            let end = run(&mut whole);
+            let expected = outcome(&whole, end);
+
+            let mut sliced = machine(ops, input, engine);
+            let mut end;
+            loop {
+                let left = BUDGET - sliced.stats().steps;
+                sliced.set_budget(Some(left.min(rng.below(20))));
+                end = run(&mut sliced);
+                if end != Ok(Status::BudgetExhausted) || left == 0 {
+                    break;
+                }
+            }
+
+            assert_eq!(outcome(&sliced, end), expected);
+            assert_eq!(sliced.ops, whole.ops);
+        }
+    });
+}
+
+#[test]
+fn agrees_with_reference() {
+    for_programs(0xa9ee, |ops, input, _| {
+        let mut reference = Reference::new(ops, input);
+        let end = reference.run(BUDGET);
+        let expected = Outcome {
+            end,
+            output: reference.output.clone(),
+            ip: reference.ip,
+            relative_base: reference.relative_base,
+            steps: reference.steps,
+        };
+
+        for &engine in [Engine::Reference, Engine::Cached].iter() {
+            let mut computer = machine(ops, input, engine);
+            computer.set_budget(Some(BUDGET));
+            let end = run(&mut computer);
+            assert_eq!(outcome(&computer, end), expected, "{:?}", engine);
+            assert!(same_memory(&computer, &reference), "{:?}", engine);
+            assert_eq!(
+                computer.pending_input(),
+                reference.input.iter().copied().collect::<Ops>().as_slice()
+            );
+        }
+    });
+}
@@ -0,0 +1,339 @@
+use crate::{Op, Result, VmError};
+use std::fmt;
+
+pub const ADD: Op = 1;
+pub const MULTIPLY: Op = 2;
+pub const INPUT: Op = 3;
+pub const OUTPUT: Op = 4;
+pub const JUMP_IF_TRUE: Op = 5;
+pub const JUMP_IF_FALSE: Op = 6;
+pub const LESS_THAN: Op = 7;
+pub const EQUALS: Op = 8;
+pub const ADJUST_RELATIVE_BASE: Op = 9;
+pub const HCF: Op = 99;
+
+/// An operand together with its addressing mode
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Param {
+    Position(Op),
+    Immediate(Op),
+    Relative(Op),
+}
+
+impl Param {
+    fn decode(ip: usize, coded_op: Op, n: u32, operand: Op) -> Result<Param> {
+        match nth_digit(n + 1, coded_op) {
+            0 => Ok(Param::Position(operand)),
+            1 => Ok(Param::Immediate(operand)),
+            2 => Ok(Param::Relative(operand)),
+            mode => Err(VmError::InvalidMode {
+                ip,
+                opcode: coded_op,
+                operand,
+                mode,
+            }),
+        }
+    }
+
+    /// The raw operand cell, whatever the mode
+    pub fn operand(self) -> Op {
+        match self {
+            Param::Position(op) | Param::Immediate(op) | Param::Relative(op) => op,
+        }
+    }
+
+    fn mode(self) -> Op {
+        match self {
+            Param::Position(_) => 0,
+            Param::Immediate(_) => 1,
+            Param::Relative(_) => 2,
+        }
+    }
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Param::Position(address) => write!(f, "[{}]", address),
+            Param::Immediate(value) => write!(f, "#{}", value),
+            Param::Relative(offset) if *offset < 0 => write!(f, "[r{}]", offset),
+            Param::Relative(offset) => write!(f, "[r+{}]", offset),
+        }
+    }
+}
+
+/// A decoded instruction. `Data` is a cell that doesn't decode, which the
+/// disassembler shows as a `db` directive.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Instruction {
+    Add(Param, Param, Param),
+    Multiply(Param, Param, Param),
+    Input(Param),
+    Output(Param),
+    JumpIfTrue(Param, Param),
+    JumpIfFalse(Param, Param),
+    LessThan(Param, Param, Param),
+    Equals(Param, Param, Param),
+    AdjustRelativeBase(Param),
+    Halt,
+    Data(Op),
+}
+
+impl Instruction {
+    /// Decodes the instruction at `ip`, fetching cells through `read`
+    pub fn decode<F: Fn(usize) -> Op>(ip: usize, read: F) -> Result<Instruction> {
+        let coded_op = read(ip);
+        let param = |n: u32| {
+            let address = (ip.checked_add(n as usize)).ok_or(VmError::IpOverflow {
+                ip,
+                opcode: coded_op,
+            })?;
+            Param::decode(ip, coded_op, n, read(address))
+        };
+
+        let instruction = match coded_op % 100 {
+            ADD => Instruction::Add(param(1)?, param(2)?, param(3)?),
+            MULTIPLY => Instruction::Multiply(param(1)?, param(2)?, param(3)?),
+            INPUT => Instruction::Input(param(1)?),
+            OUTPUT => Instruction::Output(param(1)?),
+            JUMP_IF_TRUE => Instruction::JumpIfTrue(param(1)?, param(2)?),
+            JUMP_IF_FALSE => Instruction::JumpIfFalse(param(1)?, param(2)?),
+            LESS_THAN => Instruction::LessThan(param(1)?, param(2)?, param(3)?),
+            EQUALS => Instruction::Equals(param(1)?, param(2)?, param(3)?),
+            ADJUST_RELATIVE_BASE => Instruction::AdjustRelativeBase(param(1)?),
+            HCF => Instruction::Halt,
+            _ => {
+                return Err(VmError::UnknownOpcode {
+                    ip,
+                    opcode: coded_op,
+                    operand: ip.checked_add(1).map(read),
+                })
+            }
+        };
+
+        Ok(instruction)
+    }
+
+    /// Number of cells the instruction occupies
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::Add(..)
+            | Instruction::Multiply(..)
+            | Instruction::LessThan(..)
+            | Instruction::Equals(..) => 4,
+            Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) => 3,
+            Instruction::Input(..)
+            | Instruction::Output(..)
+            | Instruction::AdjustRelativeBase(..) => 2,
+            Instruction::Halt | Instruction::Data(_) => 1,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Add(..) => "add",
+            Instruction::Multiply(..) => "mul",
+            Instruction::Input(..) => "in",
+            Instruction::Output(..) => "out",
+            Instruction::JumpIfTrue(..) => "jt",
+            Instruction::JumpIfFalse(..) => "jf",
+            Instruction::LessThan(..) => "lt",
+            Instruction::Equals(..) => "eq",
+            Instruction::AdjustRelativeBase(..) => "arb",
+            Instruction::Halt => "hlt",
+            Instruction::Data(..) => "db",
+        }
+    }
+
+    pub fn params(&self) -> Vec<Param> {
+        match *self {
+            Instruction::Add(a, b, c)
+            | Instruction::Multiply(a, b, c)
+            | Instruction::LessThan(a, b, c)
+            | Instruction::Equals(a, b, c) => vec![a, b, c],
+            Instruction::JumpIfTrue(a, b) | Instruction::JumpIfFalse(a, b) => vec![a, b],
+            Instruction::Input(a) | Instruction::Output(a) | Instruction::AdjustRelativeBase(a) => {
+                vec![a]
+            }
+            Instruction::Halt | Instruction::Data(_) => vec![],
+        }
+    }
+
+    /// Encodes back into memory cells, the inverse of `decode`
+    pub fn encode(&self) -> Vec<Op> {
+        let opcode = match self {
+            Instruction::Add(..) => ADD,
+            Instruction::Multiply(..) => MULTIPLY,
+            Instruction::Input(..) => INPUT,
+            Instruction::Output(..) => OUTPUT,
+            Instruction::JumpIfTrue(..) => JUMP_IF_TRUE,
+            Instruction::JumpIfFalse(..) => JUMP_IF_FALSE,
+            Instruction::LessThan(..) => LESS_THAN,
+            Instruction::Equals(..) => EQUALS,
+            Instruction::AdjustRelativeBase(..) => ADJUST_RELATIVE_BASE,
+            Instruction::Halt => HCF,
+            Instruction::Data(value) => return vec![*value],
+        };
+
+        let params = self.params();
+        let mut coded_op = opcode;
+        for (i, param) in params.iter().enumerate() {
+            coded_op += param.mode() * 10i64.pow(i as u32 + 2);
+        }
+
+        let mut cells = vec![coded_op];
+        cells.extend(params.iter().map(|param| param.operand()));
+        cells
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.mnemonic().to_uppercase();
+        match self {
+            Instruction::Add(a, b, c)
+            | Instruction::Multiply(a, b, c)
+            | Instruction::LessThan(a, b, c)
+            | Instruction::Equals(a, b, c) => write!(f, "{} {}, {} -> {}", name, a, b, c),
+            Instruction::JumpIfTrue(a, b) | Instruction::JumpIfFalse(a, b) => {
+                write!(f, "{} {}, {}", name, a, b)
+            }
+            Instruction::Input(a) => write!(f, "{} -> {}", name, a),
+            Instruction::Output(a) | Instruction::AdjustRelativeBase(a) => {
+                write!(f, "{} {}", name, a)
+            }
+            Instruction::Halt => write!(f, "{}", name),
+            Instruction::Data(value) => write!(f, "{} {}", name, value),
+        }
+    }
+}
+
+/// Linear sweep over a program image. Cells that don't decode, or
+/// instructions that would run off the end of the image, come back as
+/// `Instruction::Data`.
+pub fn disassemble(ops: &[Op]) -> Vec<(usize, Instruction)> {
+    let read = |address: usize| ops.get(address).copied().unwrap_or(0);
+    let mut listing = Vec::new();
+    let mut ip = 0;
+    while ip < ops.len() {
+        let instruction = match Instruction::decode(ip, read) {
+            Ok(instruction) if ip + instruction.size() <= ops.len() => instruction,
+            _ => Instruction::Data(ops[ip]),
+        };
+
+        listing.push((ip, instruction));
+        ip += instruction.size();
+    }
+
+    listing
+}
+
+pub(crate) fn nth_digit(n: u32, number: Op) -> Op {
+    (number / 10i64.pow(n)) % 10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_first_digit() {
+        let number = 43210;
+        assert_eq!(nth_digit(0, number), 0);
+        assert_eq!(nth_digit(1, number), 1);
+        assert_eq!(nth_digit(2, number), 2);
+        assert_eq!(nth_digit(3, number), 3);
+        assert_eq!(nth_digit(4, number), 4);
+    }
+
+    #[test]
+    fn verify_parse_op() {
+        let ops = [109, 5];
+        let instruction = Instruction::decode(0, |i| ops[i]);
+        assert_eq!(
+            instruction,
+            Ok(Instruction::AdjustRelativeBase(Param::Immediate(5)))
+        );
+    }
+
+    #[test]
+    fn decode_errors() {
+        let ops = [42, 301, 0, 0, 0];
+        assert_eq!(
+            Instruction::decode(0, |i| ops[i]),
+            Err(VmError::UnknownOpcode {
+                ip: 0,
+                opcode: 42,
+                operand: Some(301)
+            })
+        );
+        assert_eq!(
+            Instruction::decode(1, |i| ops[i]),
+            Err(VmError::InvalidMode {
+                ip: 1,
+                opcode: 301,
+                operand: 0,
+                mode: 3
+            })
+        );
+    }
+
+    #[test]
+    fn display() {
+        let add = Instruction::Add(
+            Param::Relative(3),
+            Param::Immediate(5),
+            Param::Position(100),
+        );
+        assert_eq!(add.to_string(), "ADD [r+3], #5 -> [100]");
+        assert_eq!(
+            Instruction::Input(Param::Relative(-2)).to_string(),
+            "IN -> [r-2]"
+        );
+        assert_eq!(Instruction::Halt.to_string(), "HLT");
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let ops = vec![21101, 3, -4, 7, 204, -1, 1006, 9, 0, 99];
+        let listing = disassemble(&ops);
+        let encoded: Vec<Op> = listing
+            .iter()
+            .flat_map(|(_, instruction)| instruction.encode())
+            .collect();
+        assert_eq!(encoded, ops);
+    }
+
+    #[test]
+    fn disassemble_listing() {
+        let ops = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+        let listing: Vec<String> = disassemble(&ops)
+            .iter()
+            .map(|(ip, instruction)| format!("{}: {}", ip, instruction))
+            .collect();
+        assert_eq!(
+            listing,
+            vec![
+                "0: IN -> [9]",
+                "2: EQ [9], [10] -> [9]",
+                "6: OUT [9]",
+                "8: HLT",
+                "9: DB -1",
+                "10: DB 8",
+            ]
+        );
+    }
+
+    #[test]
+    fn disassemble_truncated() {
+        let listing = disassemble(&[1, 2, 3]);
+        assert_eq!(
+            listing,
+            vec![
+                (0, Instruction::Data(1)),
+                (1, Instruction::Data(2)),
+                (2, Instruction::Data(3)),
+            ]
+        );
+    }
+}
@@ -1,67 +1,189 @@
-use std::error::Error;
+mod analysis;
+mod asm;
+#[cfg(feature = "bigint")]
+mod big;
+mod cache;
+mod device;
+mod error;
+mod footprint;
+#[cfg(test)]
+mod fuzz;
+mod instruction;
+mod loader;
+mod memory;
+mod network;
+mod overflow;
+mod patch;
+mod port;
+mod session;
+mod snapshot;
+mod solve;
+mod stats;
+mod symbolic;
+mod task;
+mod threaded;
+mod trace;
+
+use cache::DecodeCache;
+
+pub use analysis::{analyze, Block, ControlFlow, Subroutine, Terminator};
+pub use asm::{assemble, AsmError};
+#[cfg(feature = "bigint")]
+pub use big::{BigInt, BigIntCode, BigStatus};
+pub use cache::Engine;
+pub use device::Device;
+pub use error::VmError;
+pub use footprint::{Change, CodeWrite, Footprint, MemoryDiff};
+pub use instruction::{disassemble, Instruction, Param};
+pub use loader::{from_binary, is_binary, load, read_program, to_binary, to_text};
+pub use memory::{Memory, DEFAULT_MEMORY_LIMIT};
+pub use network::{MachineState, Network, NetworkError, Route, State, PIPELINE_OUTPUT};
+pub use overflow::Overflow;
+pub use patch::{Patch, Program};
+pub use port::{InputFn, InputPort, NullPort, OutputFn, OutputPort};
+pub use session::{Event, Session, SessionError};
+pub use snapshot::Snapshot;
+pub use solve::Solutions;
+pub use stats::Stats;
+pub use symbolic::{Assignment, Constraint, End, Expr, Linear, Path, Symbolic, Var};
+pub use task::{
+    block_on, channel, join_all, AsyncMachine, JoinAll, Next, Pipe, Receiver, Recv, Sender,
+};
+pub use threaded::{spawn, Exit, Finished, MachineThread, ThreadedNetwork};
+pub use trace::{LogTracer, RingTracer, Step, Tracer};
 
 pub type Op = i64;
-pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
+pub type Result<T> = std::result::Result<T, VmError>;
 pub type Ops = Vec<Op>;
 
 pub struct IntCode {
-    pub ops: Ops,
+    ops: Memory,
     ip: usize,
     input_pos: usize,
     relative_base: Op,
     input: Vec<Op>,
     output: Vec<Op>,
+    input_port: Option<Box<dyn InputPort + Send>>,
+    output_port: Option<Box<dyn OutputPort + Send>>,
+    tracer: Option<Box<dyn Tracer + Send>>,
+    engine: Engine,
+    cache: DecodeCache,
+    overflow: Overflow,
+    /// Instructions left before `resume` stops with `BudgetExhausted`
+    budget: Option<u64>,
+    stats: Stats,
+    /// Output from `run_device` that doesn't make a full frame yet
+    frame: Ops,
+    /// I/O recorded since `record` was called
+    session: Option<Session>,
+    /// Cells written and executed since `track_memory` was called
+    footprint: Option<Footprint>,
 }
 
-const MEMORY_SIZE: usize = 5000;
-// OpCodes - would be better as an enum, but needs lots
-// of annoying conversion logic
-const ADD: Op = 1;
-const MULTIPLY: Op = 2;
-const INPUT: Op = 3;
-const OUTPUT: Op = 4;
-const JUMP_IF_TRUE: Op = 5;
-const JUMP_IF_FALSE: Op = 6;
-const LESS_THAN: i64 = 7;
-const EQUALS: i64 = 8;
-const ADJUST_RELATIVE_BASE: Op = 9;
-const HCF: Op = 99;
-
-#[derive(PartialEq, Debug)]
-enum OpMode {
-    Positional = 0,
-    Immediate = 1,
-    Relative = 2,
-}
-
-impl From<Op> for OpMode {
-    fn from(num: Op) -> OpMode {
-        match num {
-            0 => OpMode::Positional,
-            1 => OpMode::Immediate,
-            2 => OpMode::Relative,
-            _ => panic!("invalid opmode"),
-        }
-    }
+/// Why the machine handed control back to the caller
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Status {
+    Output(Op),
+    NeedsInput,
+    Halted,
+    /// Used up the budget given to `set_budget`. Nothing was executed, so
+    /// resuming with more budget carries on exactly where it stopped.
+    BudgetExhausted,
 }
 
 impl IntCode {
-    pub fn new(program: &str) -> IntCode {
-        let ops = init_ops(program).expect("Failed parsing program");
-        IntCode::init(ops)
+    pub fn new(program: &str) -> Result<IntCode> {
+        let ops = parse_program(program)?;
+        Ok(IntCode::init(ops))
     }
 
     pub fn init(ops: Ops) -> IntCode {
+        IntCode::with_memory(Memory::new(ops))
+    }
+
+    /// A machine at address 0 of `memory`. See `Program` for spawning many
+    /// machines from one image.
+    pub fn with_memory(memory: Memory) -> IntCode {
         IntCode {
-            ops,
+            stats: Stats::new(memory.image_len()),
+            ops: memory,
             ip: 0,
             input_pos: 0,
             relative_base: 0,
             input: Vec::new(),
             output: Vec::new(),
+            input_port: None,
+            output_port: None,
+            tracer: None,
+            engine: Engine::Reference,
+            cache: DecodeCache::default(),
+            overflow: Overflow::default(),
+            budget: None,
+            frame: Vec::new(),
+            session: None,
+            footprint: None,
         }
     }
 
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+        self.cache.clear();
+    }
+
+    /// Defaults to `Overflow::Checked`, so debug and release builds agree
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Limits how many more instructions the machine may execute, `None`
+    /// for no limit. Once it's used up `resume` returns
+    /// `Status::BudgetExhausted` until the budget is raised.
+    pub fn set_budget(&mut self, steps: Option<u64>) {
+        self.budget = steps;
+    }
+
+    /// What's left of the budget
+    pub fn budget(&self) -> Option<u64> {
+        self.budget
+    }
+
+    /// Counters since the machine was created or last `reset_stats`
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::new(self.ops.image_len());
+    }
+
+    /// Attaches a tracer, replacing any previous one
+    pub fn set_tracer<T: Tracer + Send + 'static>(&mut self, tracer: T) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer + Send>> {
+        self.tracer.take()
+    }
+
+    /// Input instructions read from `port` once everything queued with
+    /// `input` has been consumed
+    pub fn set_input_port<P: InputPort + Send + 'static>(&mut self, port: P) {
+        self.input_port = Some(Box::new(port));
+    }
+
+    /// Output goes to `port` instead of being collected in `output`
+    pub fn set_output_port<P: OutputPort + Send + 'static>(&mut self, port: P) {
+        self.output_port = Some(Box::new(port));
+    }
+
+    pub fn take_input_port(&mut self) -> Option<Box<dyn InputPort + Send>> {
+        self.input_port.take()
+    }
+
+    pub fn take_output_port(&mut self) -> Option<Box<dyn OutputPort + Send>> {
+        self.output_port.take()
+    }
+
     pub fn input(&mut self, num: Op) {
         self.input.push(num);
     }
@@ -70,145 +192,430 @@ impl IntCode {
         *self.output.last().expect("No output")
     }
 
+    /// Everything output so far, unless an output port is attached
     pub fn output(&self) -> &Vec<Op> {
         &self.output
     }
 
-    pub fn run(&mut self) {
-        while let Some(_) = self.compute_output() {}
+    /// Hands over the collected output, leaving the buffer empty
+    pub fn take_output(&mut self) -> Ops {
+        std::mem::take(&mut self.output)
     }
 
-    pub fn compute_output(&mut self) -> Option<Op> {
+    pub fn memory(&self) -> &Memory {
+        &self.ops
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> Op {
+        self.relative_base
+    }
+
+    /// Inputs queued but not yet consumed
+    pub fn pending_input(&self) -> &[Op] {
+        &self.input[self.input_pos..]
+    }
+
+    /// Caps the number of memory cells the program may allocate. Writes
+    /// that would go over fail with `VmError::MemoryLimit`.
+    pub fn set_memory_limit(&mut self, cells: usize) {
+        self.ops.set_limit(cells);
+    }
+
+    /// Captures memory, registers and both I/O buffers
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.ops.clone(),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            input: self.input.clone(),
+            input_pos: self.input_pos,
+            output: self.output.clone(),
+        }
+    }
+
+    /// Puts the machine back exactly as it was when `snapshot` was taken.
+    /// Any partial `run_device` frame is dropped, and so is any recording,
+    /// which couldn't be replayed across the jump. Memory tracking carries
+    /// on from the restored memory.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.ops = snapshot.memory.clone();
+        self.ip = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.input = snapshot.input.clone();
+        self.input_pos = snapshot.input_pos;
+        self.output = snapshot.output.clone();
+        self.cache.clear();
+        self.frame.clear();
+        self.session = None;
+        if self.footprint.is_some() {
+            self.track_memory();
+        }
+    }
+
+    /// Runs until the program halts or blocks waiting for input,
+    /// collecting any output along the way.
+    pub fn run(&mut self) -> Result<Status> {
         loop {
-            let coded_op = self.ops[self.ip];
-            let (op, mode1, mode2, mode3) = op_parse(coded_op);
-            self.ip += 1;
+            match self.resume()? {
+                Status::Output(_) => {}
+                status => return Ok(status),
+            }
+        }
+    }
 
-            if op == HCF {
-                return None;
+    /// Like `resume`, but only distinguishes output from everything else.
+    /// Returns `None` when halted, waiting on input or out of budget.
+    pub fn compute_output(&mut self) -> Result<Option<Op>> {
+        match self.resume()? {
+            Status::Output(out) => Ok(Some(out)),
+            Status::NeedsInput | Status::Halted | Status::BudgetExhausted => Ok(None),
+        }
+    }
+
+    /// Executes until the next output, an input instruction with an empty
+    /// input queue, a halt, or the budget running out. When blocked on
+    /// input the instruction pointer is left on the input instruction, so
+    /// calling `resume` again after `input` picks up where it left off.
+    ///
+    /// A faulting instruction also leaves the instruction pointer in place.
+    pub fn resume(&mut self) -> Result<Status> {
+        if self.engine == Engine::Cached && self.tracer.is_none() {
+            return self.resume_cached();
+        }
+
+        loop {
+            if let Some(status) = self.step()?.status {
+                return Ok(status);
             }
+        }
+    }
 
-            match op {
-                ADD => {
-                    let a = self.op_resolve(mode1);
-                    let b = self.op_resolve(mode2);
-                    let rp = self.op_ptr(mode3);
+    /// `resume`, but stopping with `Status::BudgetExhausted` after at most
+    /// `steps` instructions. The budget from `set_budget` still applies,
+    /// and is only used up if `budget` is down to 0.
+    pub(crate) fn resume_at_most(&mut self, steps: u64) -> Result<Status> {
+        let budget = self.budget;
+        let slice = budget.map_or(steps, |budget| budget.min(steps));
+        self.budget = Some(slice);
+        let status = self.resume();
+        let used = slice - self.budget.unwrap_or(0);
+        self.budget = budget.map(|budget| budget - used);
+        status
+    }
 
-                    self.ops[rp] = a + b;
-                }
-                MULTIPLY => {
-                    let a = self.op_resolve(mode1);
-                    let b = self.op_resolve(mode2);
-                    let rp = self.op_ptr(mode3);
+    /// Executes a single instruction. Blocking on input, halting or running
+    /// out of budget doesn't move the instruction pointer, `Step::status`
+    /// says which happened.
+    pub fn step(&mut self) -> Result<Step> {
+        let decoded = match self.engine {
+            Engine::Reference => self.decode(),
+            Engine::Cached => self.cached_decode(),
+        };
+        let result = decoded.and_then(|instruction| self.execute(instruction));
+        if let (Err(error), Some(tracer)) = (&result, self.tracer.as_mut()) {
+            tracer.fault(self.ip, error);
+        }
 
-                    self.ops[rp] = a * b;
-                }
-                INPUT => {
-                    if mode1 == OpMode::Immediate {
-                        panic!("Illigal op - immediate mode imput storage");
-                    }
+        result
+    }
 
-                    let input_storage_index = self.op_ptr(mode1);
-                    self.ops[input_storage_index] = self.input[self.input_pos];
-                    self.input_pos += 1;
-                }
-                OUTPUT => {
-                    let out = self.op_resolve(mode1);
-                    self.output.push(out);
+    fn execute(&mut self, instruction: Instruction) -> Result<Step> {
+        let mut step = Step::new(self.ip, instruction);
+        if self.budget == Some(0) && instruction != Instruction::Halt {
+            step.next_ip = self.ip;
+            step.status = Some(Status::BudgetExhausted);
+            return Ok(step);
+        }
 
-                    return Some(out);
-                }
-                JUMP_IF_TRUE => {
-                    let a = self.op_resolve(mode1);
-                    let b = self.op_resolve(mode2);
+        if let Some(footprint) = self.footprint.as_mut() {
+            footprint.executing(self.ip, instruction.size());
+        }
 
-                    if a != 0 {
-                        self.ip = b as usize;
-                    }
-                }
-                JUMP_IF_FALSE => {
-                    let a = self.op_resolve(mode1);
-                    let b = self.op_resolve(mode2);
+        let mut input = None;
+        if let Instruction::Input(_) = instruction {
+            input = self.next_input();
+            if input.is_none() {
+                step.next_ip = self.ip;
+                step.status = Some(Status::NeedsInput);
+                return Ok(step);
+            }
+        }
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.before(self.ip, &instruction);
+        }
 
-                    if a == 0 {
-                        self.ip = b as usize;
+        match instruction {
+            Instruction::Add(a, b, c) => {
+                let value = self.add(step.value(self.read(a)?), step.value(self.read(b)?))?;
+                step.write = Some((self.write(c, value)?, value));
+            }
+            Instruction::Multiply(a, b, c) => {
+                let value = self.multiply(step.value(self.read(a)?), step.value(self.read(b)?))?;
+                step.write = Some((self.write(c, value)?, value));
+            }
+            Instruction::Input(a) => {
+                let value = input.unwrap();
+                match self.write(a, value) {
+                    Ok(address) => step.write = Some((address, value)),
+                    Err(error) => {
+                        self.unread(value);
+                        return Err(error);
                     }
                 }
-                LESS_THAN => {
-                    let a = self.op_resolve(mode1);
-                    let b = self.op_resolve(mode2);
-                    let rp = self.op_ptr(mode3);
 
-                    self.ops[rp] = if a < b { 1 } else { 0 };
+                if self.input_pos < self.input.len() {
+                    self.input_pos += 1;
                 }
-                EQUALS => {
-                    let a = self.op_resolve(mode1);
-                    let b = self.op_resolve(mode2);
-                    let rp = self.op_ptr(mode3);
-
-                    self.ops[rp] = if a == b { 1 } else { 0 };
+                self.stats.inputs += 1;
+                if let Some(session) = self.session.as_mut() {
+                    session.input(self.stats.steps, value);
                 }
-                ADJUST_RELATIVE_BASE => {
-                    let a = self.op_resolve(mode1);
-                    self.relative_base += a;
+            }
+            Instruction::Output(a) => {
+                let out = step.value(self.read(a)?);
+                self.stats.outputs += 1;
+                if let Some(session) = self.session.as_mut() {
+                    session.output(self.stats.steps, out);
+                }
+                match self.output_port.as_mut() {
+                    Some(port) => port.write(out),
+                    None => self.output.push(out),
                 }
-                _ => panic!("Unrecognized instruction {}", op),
-            };
+                step.status = Some(Status::Output(out));
+            }
+            Instruction::JumpIfTrue(a, b) => {
+                if step.value(self.read(a)?) != 0 {
+                    step.next_ip = self.jump_target(b, &mut step)?;
+                    self.stats.jumps += 1;
+                }
+            }
+            Instruction::JumpIfFalse(a, b) => {
+                if step.value(self.read(a)?) == 0 {
+                    step.next_ip = self.jump_target(b, &mut step)?;
+                    self.stats.jumps += 1;
+                }
+            }
+            Instruction::LessThan(a, b, c) => {
+                let value = if step.value(self.read(a)?) < step.value(self.read(b)?) {
+                    1
+                } else {
+                    0
+                };
+                step.write = Some((self.write(c, value)?, value));
+            }
+            Instruction::Equals(a, b, c) => {
+                let value = if step.value(self.read(a)?) == step.value(self.read(b)?) {
+                    1
+                } else {
+                    0
+                };
+                step.write = Some((self.write(c, value)?, value));
+            }
+            Instruction::AdjustRelativeBase(a) => {
+                self.relative_base = self.add(self.relative_base, step.value(self.read(a)?))?;
+                step.relative_base = Some(self.relative_base);
+            }
+            Instruction::Halt => {
+                step.next_ip = self.ip;
+                step.status = Some(Status::Halted);
+                if let Some(footprint) = self.footprint.as_mut() {
+                    footprint.ran();
+                }
+            }
+            Instruction::Data(_) => unreachable!("decode never yields data"),
+        };
+
+        if instruction != Instruction::Halt {
+            self.retire(&instruction);
+        }
+
+        self.ip = step.next_ip;
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.after(&step);
+        }
+
+        Ok(step)
+    }
+
+    /// Decodes the instruction under the instruction pointer
+    pub fn decode(&self) -> Result<Instruction> {
+        Instruction::decode(self.ip, |address| self.ops.get(address))
+    }
+
+    fn cached_decode(&mut self) -> Result<Instruction> {
+        self.cache.sync(self.ops.generation());
+        if let Some(instruction) = self.cache.get(self.ip) {
+            return Ok(instruction);
         }
+
+        let instruction = self.decode()?;
+        self.cache.insert(self.ip, instruction);
+        Ok(instruction)
     }
 
-    fn op_ptr(&mut self, mode: OpMode) -> usize {
-        let ip = self.ip;
-        self.ip += 1;
-        let op = self.ops[ip];
-        match mode {
-            OpMode::Positional => op as usize,
-            OpMode::Immediate => ip,
-            OpMode::Relative => (self.relative_base + op) as usize,
+    /// Counts an instruction that ran to completion against the stats, the
+    /// budget and the footprint
+    pub(crate) fn retire(&mut self, instruction: &Instruction) {
+        self.stats.count(instruction);
+        if let Some(footprint) = self.footprint.as_mut() {
+            footprint.ran();
+        }
+        if let Some(budget) = self.budget.as_mut() {
+            *budget -= 1;
         }
     }
 
-    fn op_resolve(&mut self, mode: OpMode) -> Op {
-        let ip = self.ip;
-        self.ip += 1;
+    fn read(&self, param: Param) -> Result<Op> {
+        match param {
+            Param::Immediate(value) => Ok(value),
+            _ => Ok(self.ops.get(self.address(param)?)),
+        }
+    }
+
+    /// Next input from the queue, falling back on the input port. Doesn't
+    /// advance the queue, that waits until the input instruction succeeds.
+    fn next_input(&mut self) -> Option<Op> {
+        match self.input.get(self.input_pos) {
+            Some(&value) => Some(value),
+            None => self.input_port.as_mut().and_then(|port| port.read()),
+        }
+    }
+
+    /// A value taken from the input port can't go back, so when the input
+    /// instruction faults it waits at the front of the queue instead
+    fn unread(&mut self, value: Op) {
+        if self.input_pos == self.input.len() {
+            self.input.push(value);
+        }
+    }
+
+    /// Stores `value` in the cell `param` refers to, returning its address
+    fn write(&mut self, param: Param, value: Op) -> Result<usize> {
+        let address = self.address(param)?;
+        self.ops
+            .set(address, value)
+            .map_err(|limit| VmError::MemoryLimit {
+                ip: self.ip,
+                opcode: self.ops.get(self.ip),
+                operand: param.operand(),
+                address,
+                limit,
+            })?;
+
+        self.stats.wrote(address);
+        if let Some(footprint) = self.footprint.as_mut() {
+            footprint.wrote(self.ip, address, value);
+        }
+        if self.engine == Engine::Cached {
+            self.cache.invalidate(address);
+        }
 
-        let op = self.ops[ip];
-        match mode {
-            OpMode::Positional => self.ops[op as usize],
-            OpMode::Immediate => self.ops[ip],
-            OpMode::Relative => {
-                let index: usize = (self.relative_base + op) as usize;
-                self.ops[index]
+        Ok(address)
+    }
+
+    /// Resolves a memory operand to the cell it refers to
+    fn address(&self, param: Param) -> Result<usize> {
+        let address = match param {
+            Param::Position(address) => address,
+            Param::Relative(offset) => self.add(self.relative_base, offset)?,
+            Param::Immediate(operand) => {
+                return Err(VmError::ImmediateWrite {
+                    ip: self.ip,
+                    opcode: self.ops.get(self.ip),
+                    operand,
+                })
             }
+        };
+
+        self.checked_address(param.operand(), address)
+    }
+
+    fn add(&self, a: Op, b: Op) -> Result<Op> {
+        self.overflow.add(a, b).ok_or_else(|| self.overflowed(a, b))
+    }
+
+    fn multiply(&self, a: Op, b: Op) -> Result<Op> {
+        self.overflow
+            .multiply(a, b)
+            .ok_or_else(|| self.overflowed(a, b))
+    }
+
+    fn overflowed(&self, a: Op, b: Op) -> VmError {
+        VmError::Overflow {
+            ip: self.ip,
+            opcode: self.ops.get(self.ip),
+            a,
+            b,
         }
     }
-}
 
-fn nth_digit(n: u32, number: Op) -> Op {
-    (number / 10i64.pow(n)) % 10
-}
+    fn jump_target(&self, param: Param, step: &mut Step) -> Result<usize> {
+        let target = step.value(self.read(param)?);
+        self.checked_address(target, target)
+    }
 
-fn init_ops(input: &str) -> Result<Ops> {
-    let mut ops: Vec<Op> = Vec::with_capacity(MEMORY_SIZE);
-    for op_str in input.split(',') {
-        let op: Op = op_str.trim().parse()?;
-        ops.push(op);
+    fn target(&self, param: Param) -> Result<usize> {
+        let target = self.read(param)?;
+        self.checked_address(target, target)
     }
 
-    for _ in ops.len()..MEMORY_SIZE {
-        ops.push(0);
+    fn checked_address(&self, operand: Op, address: Op) -> Result<usize> {
+        if address < 0 {
+            return Err(VmError::NegativeAddress {
+                ip: self.ip,
+                opcode: self.ops.get(self.ip),
+                operand,
+                address,
+            });
+        }
+
+        Ok(address as usize)
     }
+}
 
-    Ok(ops)
+/// Clones don't inherit the tracer or I/O ports
+impl Clone for IntCode {
+    fn clone(&self) -> IntCode {
+        IntCode {
+            ops: self.ops.clone(),
+            ip: self.ip,
+            input_pos: self.input_pos,
+            relative_base: self.relative_base,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            input_port: None,
+            output_port: None,
+            tracer: None,
+            engine: self.engine,
+            cache: self.cache.clone(),
+            overflow: self.overflow,
+            budget: self.budget,
+            stats: self.stats.clone(),
+            frame: self.frame.clone(),
+            session: self.session.clone(),
+            footprint: self.footprint.clone(),
+        }
+    }
 }
 
-fn op_parse(coded_op: Op) -> (Op, OpMode, OpMode, OpMode) {
-    let op = coded_op % 100;
-    let mode1 = OpMode::from(nth_digit(2, coded_op));
-    let mode2 = OpMode::from(nth_digit(3, coded_op));
-    let mode3 = OpMode::from(nth_digit(4, coded_op));
+/// Parses comma separated program text into an image
+pub fn parse_program(input: &str) -> Result<Ops> {
+    let mut ops: Vec<Op> = Vec::new();
+    for (index, op_str) in input.split(',').enumerate() {
+        let token = op_str.trim();
+        let op: Op = token.parse().map_err(|_| VmError::Parse {
+            index,
+            token: token.to_string(),
+        })?;
+        ops.push(op);
+    }
 
-    (op, mode1, mode2, mode3)
+    Ok(ops)
 }
 
 #[cfg(test)]
@@ -230,11 +637,22 @@ mod tests {
             1008,1000,1,63,
             1005,63,65,1008,1000,2,63,1005,63,904,1008,1000,0,63,1005,63,58,4,25,104,0,99,4,0,104,0,99,4,17,104,0,99,0,0,1102,1,39,1013,1102,1,21,1018,1101,0,336,1027,1102,1,38,1012,1101,534,0,1025,1101,539,0,1024,1101,0,380,1023,1102,1,23,1014,1102,29,1,1000,1102,24,1,1019,1102,1,28,1011,1101,339,0,1026,1101,31,0,1005,1102,36,1,1017,1102,26,1,1007,1102,1,407,1028,1101,387,0,1022,1101,0,30,1001,1101,34,0,1010,1102,1,32,1006,1101,0,1,1021,1102,27,1,1008,1102,22,1,1004,1102,1,20,1015,1101,0,37,1016,1101,0,0,1020,1102,1,398,1029,1101,25,0,1009,1101,0,35,1003,1101,33,0,1002,109,27,1206,-6,197,1001,64,1,64,1105,1,199,4,187,1002,64,2,64,109,-22,2107,26,3,63,1005,63,217,4,205,1105,1,221,1001,64,1,64,1002,64,2,64,109,17,21107,40,39,-8,1005,1014,241,1001,64,1,64,1105,1,243,4,227,1002,64,2,64,109,-8,1206,6,261,4,249,1001,64,1,64,1106,0,261,1002,64,2,64,109,-7,2108,24,0,63,1005,63,281,1001,64,1,64,1105,1,283,4,267,1002,64,2,64,109,11,21102,41,1,-3,1008,1015,42,63,1005,63,303,1105,1,309,4,289,1001,64,1,64,1002,64,2,64,109,1,1205,2,327,4,315,1001,64,1,64,1105,1,327,1002,64,2,64,109,10,2106,0,-2,1106,0,345,4,333,1001,64,1,64,1002,64,2,64,109,-15,21102,42,1,3,1008,1017,42,63,1005,63,367,4,351,1105,1,371,1001,64,1,64,1002,64,2,64,109,-1,2105,1,10,1001,64,1,64,1105,1,389,4,377,1002,64,2,64,109,24,2106,0,-9,4,395,1001,64,1,64,1105,1,407,1002,64,2,64,109,-30,1208,-2,32,63,1005,63,427,1001,64,1,64,1106,0,429,4,413,1002,64,2,64,109,2,1201,0,0,63,1008,63,27,63,1005,63,449,1106,0,455,4,435,1001,64,1,64,1002,64,2,64,109,5,21107,43,44,0,1005,1014,473,4,461,1106,0,477,1001,64,1,64,1002,64,2,64,109,-16,1202,3,1,63,1008,63,33,63,1005,63,501,1001,64,1,64,1106,0,503,4,483,1002,64,2,64,109,10,1207,-4,21,63,1005,63,523,1001,64,1,64,1106,0,525,4,509,1002,64,2,64,109,11,2105,1,5,4,531,1106,0,543,1001,64,1,64,1002,64,2,64,109,-8,21101,44,0,5,1008,1016,47,63,1005,63,563,1106,0,569,4,549,1001,64,1,64,1002,64,2,64,109,-13,2102,1,8,63,1008,63,34,63,1005,63,593,1001,64,1,64,1105,1,595,4,575,1002,64,2,64,109,8,1208,-1,31,63,1005,63,617,4,601,1001,64,1,64,1106,0,617,1002,64,2,64,109,-8,2108,33,4,63,1005,63,635,4,623,1105,1,639,1001,64,1,64,1002,64,2,64,109,10,1202,-1,1,63,1008,63,26,63,1005,63,665,4,645,1001,64,1,64,1105,1,665,1002,64,2,64,109,-9,2107,30,1,63,1005,63,685,1001,64,1,64,1105,1,687,4,671,1002,64,2,64,109,25,1205,-4,703,1001,64,1,64,1105,1,705,4,693,1002,64,2,64,109,-19,2101,0,-5,63,1008,63,26,63,1005,63,725,1105,1,731,4,711,1001,64,1,64,1002,64,2,64,109,6,1207,-2,26,63,1005,63,749,4,737,1105,1,753,1001,64,1,64,1002,64,2,64,109,-10,21108,45,46,9,1005,1010,769,1105,1,775,4,759,1001,64,1,64,1002,64,2,64,109,-10,1201,10,0,63,1008,63,30,63,1005,63,801,4,781,1001,64,1,64,1106,0,801,1002,64,2,64,109,21,21108,46,46,3,1005,1015,819,4,807,1106,0,823,1001,64,1,64,1002,64,2,64,109,-4,2102,1,-3,63,1008,63,31,63,1005,63,849,4,829,1001,64,1,64,1106,0,849,1002,64,2,64,109,-5,2101,0,1,63,1008,63,22,63,1005,63,875,4,855,1001,64,1,64,1105,1,875,1002,64,2,64,109,17,21101,47,0,-3,1008,1017,47,63,1005,63,897,4,881,1105,1,901,1001,64,1,64,4,64,99,21101,0,27,1,21102,1,915,0,1105,1,922,21201,1,38480,1,204,1,99,109,3,1207,-2,3,63,1005,63,964,21201,-2,-1,1,21101,0,942,0,1106,0,922,21202,1,1,-1,21201,-2,-3,1,21101,957,0,0,1105,1,922,22201,1,-1,-2,1106,0,968,22101,0,-2,-2,109,-3,2105,1,0";
 
+    /// Runs on both engines, checking the cached one against the reference
     fn run_program(program: &str, input: Op) -> Op {
-        let mut computer = IntCode::new(program);
+        let mut computer = IntCode::new(program).unwrap();
         computer.input(input);
-        computer.run();
+        computer.run().unwrap();
         println!("output: {:?}", computer.output);
+
+        let mut cached = IntCode::new(program).unwrap();
+        cached.set_engine(Engine::Cached);
+        cached.input(input);
+        cached.run().unwrap();
+        assert_eq!(cached.output, computer.output);
+        assert_eq!(cached.ops, computer.ops);
+        assert_eq!(cached.ip, computer.ip);
+        assert_eq!(cached.relative_base, computer.relative_base);
+
         computer.last_output()
     }
 
@@ -252,11 +670,11 @@ mod tests {
 
     #[test]
     fn two_1() {
-        let mut computer = IntCode::new(PROGRAM_2);
+        let mut computer = IntCode::new(PROGRAM_2).unwrap();
         computer.ops[1] = 12;
         computer.ops[2] = 2;
         computer.input(1);
-        computer.run();
+        computer.run().unwrap();
 
         assert_eq!(5_482_655, computer.ops[0]);
     }
@@ -319,8 +737,8 @@ mod tests {
             1008,100,16,101,
             1006,101,0,
             99";
-        let mut computer = IntCode::new(program);
-        computer.run();
+        let mut computer = IntCode::new(program).unwrap();
+        computer.run().unwrap();
         assert_eq!(
             computer.output,
             [109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99]
@@ -342,20 +760,204 @@ mod tests {
     }
 
     #[test]
-    fn find_first_digit() {
-        let number = 43210;
-        assert_eq!(nth_digit(0, number), 0);
-        assert_eq!(nth_digit(1, number), 1);
-        assert_eq!(nth_digit(2, number), 2);
-        assert_eq!(nth_digit(3, number), 3);
-        assert_eq!(nth_digit(4, number), 4);
+    fn blocks_on_empty_input() {
+        let mut computer = IntCode::new("3,9,8,9,10,9,4,9,99,-1,8").unwrap();
+        assert_eq!(computer.resume(), Ok(Status::NeedsInput));
+        assert_eq!(computer.resume(), Ok(Status::NeedsInput));
+
+        computer.input(8);
+        assert_eq!(computer.resume(), Ok(Status::Output(1)));
+        assert_eq!(computer.resume(), Ok(Status::Halted));
+        assert_eq!(computer.resume(), Ok(Status::Halted));
+    }
+
+    #[test]
+    fn run_stops_for_each_input() {
+        let program = "3,0,4,0,3,0,4,0,99";
+        let mut computer = IntCode::new(program).unwrap();
+        assert_eq!(computer.run(), Ok(Status::NeedsInput));
+        computer.input(5);
+        assert_eq!(computer.run(), Ok(Status::NeedsInput));
+        computer.input(7);
+        assert_eq!(computer.run(), Ok(Status::Halted));
+        assert_eq!(computer.output(), &vec![5, 7]);
+    }
+
+    fn run_error(program: &str) -> VmError {
+        let mut computer = IntCode::new(program).unwrap();
+        computer.input(1);
+        computer.run().unwrap_err()
+    }
+
+    #[test]
+    fn parse_error() {
+        let err = IntCode::new("1,2,x3,4").err();
+        assert_eq!(
+            err,
+            Some(VmError::Parse {
+                index: 2,
+                token: "x3".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let err = run_error("1101,20,22,4,0");
+        assert_eq!(
+            err,
+            VmError::UnknownOpcode {
+                ip: 4,
+                opcode: 42,
+                operand: Some(0)
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_mode() {
+        let err = run_error("104,7,301,5,0,0");
+        assert_eq!(
+            err,
+            VmError::InvalidMode {
+                ip: 2,
+                opcode: 301,
+                operand: 5,
+                mode: 3
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "invalid parameter mode 3 in opcode 301 at ip 2 (operand 5)"
+        );
+    }
+
+    #[test]
+    fn immediate_write() {
+        let err = run_error("103,5,99");
+        assert_eq!(
+            err,
+            VmError::ImmediateWrite {
+                ip: 0,
+                opcode: 103,
+                operand: 5
+            }
+        );
+    }
+
+    #[test]
+    fn negative_address() {
+        let err = run_error("109,-10,204,3,99");
+        assert_eq!(
+            err,
+            VmError::NegativeAddress {
+                ip: 2,
+                opcode: 204,
+                operand: 3,
+                address: -7
+            }
+        );
+    }
+
+    #[test]
+    fn clone_forks_machine() {
+        let program = "3,0,4,0,3,0,4,0,99";
+        let mut computer = IntCode::new(program).unwrap();
+        computer.input(1);
+        assert_eq!(computer.run(), Ok(Status::NeedsInput));
+
+        let mut fork = computer.clone();
+        computer.input(2);
+        fork.input(3);
+        assert_eq!(computer.run(), Ok(Status::Halted));
+        assert_eq!(fork.run(), Ok(Status::Halted));
+        assert_eq!(computer.output(), &vec![1, 2]);
+        assert_eq!(fork.output(), &vec![1, 3]);
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let mut computer = IntCode::new(PROGRAM_9).unwrap();
+        computer.input(1);
+        for _ in 0..3 {
+            computer.resume().unwrap();
+        }
+
+        let snapshot = computer.snapshot();
+        computer.record();
+        computer.track_memory();
+        computer.run().unwrap();
+        let finished = computer.output().clone();
+
+        computer.restore(&snapshot);
+        assert_eq!(computer.snapshot(), snapshot);
+        assert_eq!(computer.session(), None);
+        assert_eq!(computer.memory_diff().unwrap().to_string(), "");
+        computer.run().unwrap();
+        assert_eq!(computer.output(), &finished);
+
+        let mut restored = IntCode::from(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap());
+        restored.run().unwrap();
+        assert_eq!(restored.output(), &finished);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut computer = IntCode::new("1101,1,1,100000,99").unwrap();
+        computer.set_memory_limit(5);
+        assert_eq!(
+            computer.run(),
+            Err(VmError::MemoryLimit {
+                ip: 0,
+                opcode: 1101,
+                operand: 100_000,
+                address: 100_000,
+                limit: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn high_memory_grows() {
+        let program = "1101,5,6,1000000000,4,1000000000,4,2000000000,99";
+        let mut computer = IntCode::new(program).unwrap();
+        computer.run().unwrap();
+        assert_eq!(computer.output(), &vec![11, 0]);
+        assert_eq!(computer.ops.used(), 10);
+    }
+
+    #[test]
+    fn fault_leaves_ip_on_instruction() {
+        let mut computer = IntCode::new("104,1,42").unwrap();
+        assert_eq!(computer.resume(), Ok(Status::Output(1)));
+        assert!(computer.resume().is_err());
+        assert!(computer.resume().is_err());
+    }
+
+    #[test]
+    fn cached_self_modifying() {
+        // Outputs [20], then rewrites the OUT's operand to 21 and jumps back
+        let program = "4,20,1101,0,21,1,1105,1,0,0,0,0,0,0,0,0,0,0,0,0,7,9";
+        let mut reference = IntCode::new(program).unwrap();
+        let mut cached = IntCode::new(program).unwrap();
+        cached.set_engine(Engine::Cached);
+        // A tracer takes the cached engine off its fast path
+        let mut traced = cached.clone();
+        traced.set_tracer(RingTracer::new(1));
+        for computer in [&mut reference, &mut cached, &mut traced].iter_mut() {
+            assert_eq!(computer.resume(), Ok(Status::Output(7)));
+            assert_eq!(computer.resume(), Ok(Status::Output(9)));
+        }
+        assert_eq!(cached.ops, reference.ops);
+        assert_eq!(traced.ops, reference.ops);
     }
 
     #[test]
-    fn verify_parse_op() {
-        let coded_op = 109;
-        let (op, mode1, _, _) = op_parse(coded_op);
-        assert_eq!(op, ADJUST_RELATIVE_BASE);
-        assert_eq!(mode1, OpMode::Immediate);
+    fn cached_sees_host_pokes() {
+        let mut computer = IntCode::new("104,1,1105,1,0").unwrap();
+        computer.set_engine(Engine::Cached);
+        assert_eq!(computer.resume(), Ok(Status::Output(1)));
+        computer.ops[1] = 2;
+        assert_eq!(computer.resume(), Ok(Status::Output(2)));
     }
 }
@@ -0,0 +1,206 @@
+use crate::{parse_program, IntCode, Op, Ops, Result, VmError};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"ICIM";
+const VERSION: u8 = 1;
+
+/// Longest varint a 64 bit value can need
+const MAX_VARINT: usize = 10;
+
+/// Reads a program from a file, in either text or binary form
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Ops> {
+    read_program(File::open(path)?)
+}
+
+/// Reads a whole program from `reader`. Anything starting with the binary
+/// image magic is decoded as an image, everything else is parsed as comma
+/// separated text.
+pub fn read_program<R: Read>(mut reader: R) -> Result<Ops> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if is_binary(&bytes) {
+        return from_binary(&bytes);
+    }
+
+    let text = std::str::from_utf8(&bytes).map_err(|error| VmError::Corrupt {
+        offset: error.valid_up_to(),
+        reason: "program text isn't UTF-8",
+    })?;
+    parse_program(text.trim())
+}
+
+/// Whether `bytes` look like a binary image rather than text
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Encodes a program as a binary image: `ICIM`, a version byte, the number
+/// of ops as a varint, then each op as a zigzag varint
+pub fn to_binary(ops: &[Op]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(ops.len() * 2 + 8);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    put_varint(&mut bytes, ops.len() as u64);
+    for &op in ops {
+        // Zigzag keeps small negative numbers small
+        put_varint(&mut bytes, ((op << 1) ^ (op >> 63)) as u64);
+    }
+
+    bytes
+}
+
+pub fn from_binary(bytes: &[u8]) -> Result<Ops> {
+    if !is_binary(bytes) {
+        return Err(corrupt(0, "not a program image"));
+    }
+
+    if bytes.get(4) != Some(&VERSION) {
+        return Err(corrupt(4, "unsupported image version"));
+    }
+
+    let mut offset = 5;
+    let len = varint(bytes, &mut offset)?;
+    // Every op takes at least a byte, so don't trust a length that says
+    // otherwise before allocating for it
+    if len > (bytes.len() - offset) as u64 {
+        return Err(corrupt(5, "length runs past end of image"));
+    }
+
+    let mut ops = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        let zigzag = varint(bytes, &mut offset)?;
+        ops.push((zigzag >> 1) as Op ^ -((zigzag & 1) as Op));
+    }
+
+    if offset != bytes.len() {
+        return Err(corrupt(offset, "trailing bytes"));
+    }
+
+    Ok(ops)
+}
+
+/// Comma separated text, the form `parse_program` reads
+pub fn to_text(ops: &[Op]) -> String {
+    let tokens: Vec<String> = ops.iter().map(Op::to_string).collect();
+    tokens.join(",")
+}
+
+impl IntCode {
+    /// A machine running the program in the file at `path`, see `load`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<IntCode> {
+        Ok(IntCode::init(load(path)?))
+    }
+
+    /// A machine running the program read from `reader`, see `read_program`
+    pub fn from_reader<R: Read>(reader: R) -> Result<IntCode> {
+        Ok(IntCode::init(read_program(reader)?))
+    }
+}
+
+fn put_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    bytes.push(value as u8);
+}
+
+fn varint(bytes: &[u8], offset: &mut usize) -> Result<u64> {
+    let start = *offset;
+    let mut value = 0u64;
+    for shift in 0..MAX_VARINT {
+        let byte = *bytes
+            .get(*offset)
+            .ok_or_else(|| corrupt(*offset, "unexpected end of image"))?;
+        *offset += 1;
+
+        let bits = u64::from(byte & 0x7f);
+        if shift == MAX_VARINT - 1 && bits > 1 {
+            return Err(corrupt(start, "varint out of range"));
+        }
+
+        value |= bits << (7 * shift);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(corrupt(start, "varint out of range"))
+}
+
+fn corrupt(offset: usize, reason: &'static str) -> VmError {
+    VmError::Corrupt { offset, reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUINE: &str = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+
+    #[test]
+    fn binary_round_trip() {
+        let ops = vec![0, 1, -1, 63, -64, 64, 1 << 40, Op::MAX, Op::MIN];
+        assert_eq!(from_binary(&to_binary(&ops)), Ok(ops));
+
+        let quine = parse_program(QUINE).unwrap();
+        let binary = to_binary(&quine);
+        assert!(binary.len() < QUINE.len());
+        assert_eq!(to_text(&from_binary(&binary).unwrap()), QUINE);
+    }
+
+    #[test]
+    fn corrupt_images() {
+        let binary = to_binary(&[300, 2]);
+        assert_eq!(
+            from_binary(&binary[..8]),
+            Err(corrupt(8, "unexpected end of image"))
+        );
+        assert_eq!(
+            from_binary(&binary[..7]),
+            Err(corrupt(5, "length runs past end of image"))
+        );
+
+        let mut long = binary.clone();
+        long.push(0);
+        assert_eq!(from_binary(&long), Err(corrupt(9, "trailing bytes")));
+
+        let mut huge = b"ICIM\x01\x01".to_vec();
+        huge.extend_from_slice(&[0xff; 10]);
+        assert_eq!(from_binary(&huge), Err(corrupt(6, "varint out of range")));
+        assert_eq!(
+            from_binary(b"ICSN\x01"),
+            Err(corrupt(0, "not a program image"))
+        );
+    }
+
+    #[test]
+    fn reader_detects_format() {
+        let text = format!("{}\n", QUINE);
+        let ops = read_program(text.as_bytes()).unwrap();
+        assert_eq!(read_program(&to_binary(&ops)[..]), Ok(ops));
+
+        assert_eq!(
+            read_program("1,2,x,4".as_bytes()),
+            Err(VmError::Parse {
+                index: 2,
+                token: "x".to_string()
+            })
+        );
+        assert_eq!(
+            read_program(&b"1,2\xff"[..]),
+            Err(corrupt(3, "program text isn't UTF-8"))
+        );
+    }
+
+    #[test]
+    fn missing_file() {
+        match IntCode::from_path("/nonexistent/program.txt") {
+            Err(VmError::Io { kind, .. }) => assert_eq!(kind, std::io::ErrorKind::NotFound),
+            _ => panic!("expected an io error"),
+        }
+    }
+}
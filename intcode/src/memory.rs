@@ -0,0 +1,306 @@
+use crate::{Op, Ops};
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
+
+/// Default cap on allocated cells, 8MB worth of `Op`s
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 20;
+
+static ZERO: Op = 0;
+
+/// Writes at most this far past the end of the image grow the image to
+/// reach them rather than going into the sparse map, keeping stacks and
+/// scratch space just after the program in fast dense memory
+const DENSE_GAP: usize = 1024;
+
+/// Cells per page of dense memory
+const PAGE_SIZE: usize = 256;
+
+type Page = [Op; PAGE_SIZE];
+
+/// Machine memory. The program image is kept dense, anything written past
+/// the end of it lands in a sparse map so high addresses cost one cell
+/// each. Cells that were never written read as 0.
+///
+/// The dense part is split into shared pages that are copied on first
+/// write, so cloning is cheap and clones only pay for what they change.
+#[derive(Clone, Debug)]
+pub struct Memory {
+    pages: Vec<Arc<Page>>,
+    /// Cells in the dense part. Cells past it on the last page are 0.
+    len: usize,
+    sparse: HashMap<usize, Op>,
+    limit: usize,
+    /// Bumped on every direct poke through `IndexMut`
+    generation: u64,
+}
+
+impl Memory {
+    pub fn new(image: Ops) -> Memory {
+        Memory::from_parts(image, HashMap::new(), DEFAULT_MEMORY_LIMIT)
+    }
+
+    pub(crate) fn from_parts(image: Ops, sparse: HashMap<usize, Op>, limit: usize) -> Memory {
+        let pages = image
+            .chunks(PAGE_SIZE)
+            .map(|chunk| {
+                let mut page = [0; PAGE_SIZE];
+                page[..chunk.len()].copy_from_slice(chunk);
+                Arc::new(page)
+            })
+            .collect();
+
+        Memory {
+            pages,
+            len: image.len(),
+            sparse,
+            limit,
+            generation: 0,
+        }
+    }
+
+    /// A copy of the dense part of memory, the program image plus anything
+    /// written contiguously after it
+    pub fn image(&self) -> Ops {
+        let mut image: Ops = self
+            .pages
+            .iter()
+            .flat_map(|page| page.iter())
+            .copied()
+            .collect();
+        image.truncate(self.len);
+        image
+    }
+
+    /// Number of cells in `image`
+    pub fn image_len(&self) -> usize {
+        self.len
+    }
+
+    /// Cells allocated above the image, in address order
+    pub fn sparse_cells(&self) -> Vec<(usize, Op)> {
+        let mut cells: Vec<(usize, Op)> = self.sparse.iter().map(|(&a, &v)| (a, v)).collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    pub fn get(&self, address: usize) -> Op {
+        if address < self.len {
+            self.pages[address / PAGE_SIZE][address % PAGE_SIZE]
+        } else {
+            *self.sparse.get(&address).unwrap_or(&ZERO)
+        }
+    }
+
+    /// Writes a cell, allocating it if needed. Fails without writing if
+    /// allocating would take memory use past the limit.
+    pub fn set(&mut self, address: usize, value: Op) -> Result<(), usize> {
+        if let Some(cell) = self.cell_mut(address) {
+            *cell = value;
+            return Ok(());
+        }
+
+        if self.used() >= self.limit {
+            return Err(self.limit);
+        }
+
+        *self.allocate(address) = value;
+        Ok(())
+    }
+
+    /// Number of allocated cells
+    pub fn used(&self) -> usize {
+        self.len + self.sparse.len()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Changes whenever a cell may have been modified through `IndexMut`,
+    /// which `set` doesn't count
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn cell_mut(&mut self, address: usize) -> Option<&mut Op> {
+        if address < self.len {
+            let page = Arc::make_mut(&mut self.pages[address / PAGE_SIZE]);
+            Some(&mut page[address % PAGE_SIZE])
+        } else {
+            self.sparse.get_mut(&address)
+        }
+    }
+
+    fn allocate(&mut self, address: usize) -> &mut Op {
+        let gap = address - self.len;
+        if gap >= DENSE_GAP || self.used() + gap >= self.limit {
+            return self.sparse.entry(address).or_insert(0);
+        }
+
+        // Writes near the end of the image extend it, pulling in any sparse
+        // cells that are now inside it or contiguous with it.
+        let start = self.len;
+        self.grow(address + 1);
+        if !self.sparse.is_empty() {
+            for image_address in start..self.len {
+                if let Some(value) = self.sparse.remove(&image_address) {
+                    *self.cell_mut(image_address).unwrap() = value;
+                }
+            }
+
+            while let Some(value) = self.sparse.remove(&self.len) {
+                self.grow(self.len + 1);
+                *self.cell_mut(self.len - 1).unwrap() = value;
+            }
+        }
+
+        self.cell_mut(address).unwrap()
+    }
+
+    /// Extends the dense part to `len` cells of 0
+    fn grow(&mut self, len: usize) {
+        while self.pages.len() * PAGE_SIZE < len {
+            self.pages.push(Arc::new([0; PAGE_SIZE]));
+        }
+        self.len = len;
+    }
+}
+
+/// Contents only. A cell that was never allocated equals one holding 0,
+/// and neither the limit nor which cells are dense or sparse matters.
+impl PartialEq for Memory {
+    fn eq(&self, other: &Memory) -> bool {
+        let same = |address: &usize| self.get(*address) == other.get(*address);
+        let dense = if self.len == other.len {
+            self.pages == other.pages
+        } else {
+            (0..self.len.max(other.len)).all(|address| same(&address))
+        };
+
+        dense && self.sparse.keys().all(same) && other.sparse.keys().all(same)
+    }
+}
+
+impl From<Ops> for Memory {
+    fn from(image: Ops) -> Memory {
+        Memory::new(image)
+    }
+}
+
+impl Index<usize> for Memory {
+    type Output = Op;
+
+    fn index(&self, address: usize) -> &Op {
+        if address < self.len {
+            &self.pages[address / PAGE_SIZE][address % PAGE_SIZE]
+        } else {
+            self.sparse.get(&address).unwrap_or(&ZERO)
+        }
+    }
+}
+
+/// Direct pokes from the host always allocate, ignoring the limit
+impl IndexMut<usize> for Memory {
+    fn index_mut(&mut self, address: usize) -> &mut Op {
+        self.generation += 1;
+        if self.cell_mut(address).is_some() {
+            return self.cell_mut(address).unwrap();
+        }
+
+        self.allocate(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unwritten_reads_zero() {
+        let memory = Memory::new(vec![1, 2, 3]);
+        assert_eq!(memory.get(1), 2);
+        assert_eq!(memory.get(3), 0);
+        assert_eq!(memory[1_000_000], 0);
+        assert_eq!(memory.used(), 3);
+    }
+
+    #[test]
+    fn sparse_writes() {
+        let mut memory = Memory::new(vec![1, 2, 3]);
+        memory.set(1_000_000, 7).unwrap();
+        memory.set(1_000_000, 8).unwrap();
+        assert_eq!(memory.get(1_000_000), 8);
+        assert_eq!(memory.used(), 4);
+    }
+
+    #[test]
+    fn contiguous_writes_extend_image() {
+        let mut memory = Memory::new(vec![1]);
+        memory.set(2, 3).unwrap();
+        memory.set(1, 2).unwrap();
+        assert_eq!(memory.image(), vec![1, 2, 3]);
+        assert!(memory.sparse.is_empty());
+    }
+
+    #[test]
+    fn small_gaps_stay_dense() {
+        let mut memory = Memory::new(vec![1]);
+        memory.set(50, 2).unwrap();
+        memory.set(5000, 3).unwrap();
+        assert_eq!(memory.image_len(), 51);
+        assert_eq!(memory.sparse_cells(), vec![(5000, 3)]);
+        assert_eq!(memory.get(25), 0);
+    }
+
+    #[test]
+    fn limit() {
+        let mut memory = Memory::new(vec![1, 2]);
+        memory.set_limit(3);
+        assert_eq!(memory.set(10, 1), Ok(()));
+        assert_eq!(memory.set(11, 1), Err(3));
+        assert_eq!(memory.set(10, 2), Ok(()));
+        assert_eq!(memory.set(0, 2), Ok(()));
+        assert_eq!(memory.get(11), 0);
+    }
+
+    #[test]
+    fn equal_by_contents() {
+        let mut memory = Memory::new(vec![1, 0, 0]);
+        let mut other = Memory::new(vec![1]);
+        other.set(1 << 40, 0).unwrap();
+        other.set_limit(10);
+        assert_eq!(memory, other);
+
+        memory.set(1 << 40, 4).unwrap();
+        assert_ne!(memory, other);
+        other.set(1 << 40, 4).unwrap();
+        assert_eq!(memory, other);
+        other.set(2, 5).unwrap();
+        assert_ne!(memory, other);
+    }
+
+    #[test]
+    fn clones_share_unwritten_pages() {
+        let mut memory = Memory::new((0..1000).collect());
+        let mut clone = memory.clone();
+        clone.set(300, -1).unwrap();
+        clone.set(1000, -2).unwrap();
+        assert_eq!((memory.get(300), clone.get(300)), (300, -1));
+        assert_eq!(clone.get(1000), -2);
+
+        let shared = |a: &Memory, b: &Memory| {
+            let pairs = a.pages.iter().zip(b.pages.iter());
+            pairs.map(|(a, b)| Arc::ptr_eq(a, b)).collect::<Vec<_>>()
+        };
+        assert_eq!(shared(&memory, &clone), vec![true, false, true, false]);
+
+        memory[5] = 6;
+        assert_eq!(shared(&memory, &clone), vec![false, false, true, false]);
+        assert_eq!(clone.get(5), 5);
+    }
+}
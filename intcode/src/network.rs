@@ -0,0 +1,535 @@
+use crate::{IntCode, NullPort, Op, Status, VmError};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+/// Channel a pipeline's last machine writes to
+pub const PIPELINE_OUTPUT: &str = "out";
+
+/// Rounds without any machine making progress before the network counts as
+/// deadlocked. Machines fed an idle value never block outright, so one
+/// quiet round isn't enough to be sure.
+const DEFAULT_IDLE_ROUNDS: usize = 2;
+
+/// Most steps a machine runs in one turn, so one that never blocks or
+/// halts can't keep the others from running
+const DEFAULT_TURN_STEPS: u64 = 100_000;
+
+/// Where a machine's output goes
+#[derive(PartialEq, Debug, Clone)]
+pub enum Route {
+    /// Every value goes to the named channel
+    Channel(String),
+    /// Output comes in frames of an address followed by `payload` values.
+    /// The payload goes to the channel named by the address.
+    Packets {
+        payload: usize,
+    },
+    Discard,
+}
+
+/// What a machine was doing when the network stopped
+#[derive(PartialEq, Debug, Clone)]
+pub enum State {
+    /// Hasn't had a turn yet, or used up its steps in the last one
+    Ready,
+    /// Waiting on an empty channel
+    Blocked {
+        channel: String,
+    },
+    Halted,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct MachineState {
+    pub name: String,
+    pub ip: usize,
+    pub state: State,
+}
+
+impl fmt::Display for MachineState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.state {
+            State::Ready => write!(f, "{} ready at ip {}", self.name, self.ip),
+            State::Blocked { channel } => write!(
+                f,
+                "{} blocked on channel {} at ip {}",
+                self.name, channel, self.ip
+            ),
+            State::Halted => write!(f, "{} halted at ip {}", self.name, self.ip),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum NetworkError {
+    Fault {
+        machine: String,
+        error: VmError,
+    },
+    /// Nothing can make progress but not everything has halted
+    Deadlock(Vec<MachineState>),
+    /// A machine used up the budget it was given with `IntCode::set_budget`
+    OutOfBudget {
+        machine: String,
+    },
+    /// Nothing read any input for longer than a threaded network's idle
+    /// timeout, so the machines still going were stopped
+    TimedOut(Vec<MachineState>),
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Fault { machine, error } => write!(f, "{}: {}", machine, error),
+            NetworkError::OutOfBudget { machine } => write!(f, "{}: out of budget", machine),
+            NetworkError::Deadlock(states) => write_states(f, "deadlock", states),
+            NetworkError::TimedOut(states) => write_states(f, "timed out", states),
+        }
+    }
+}
+
+/// `what`, then a line for each machine
+fn write_states(f: &mut fmt::Formatter, what: &str, states: &[MachineState]) -> fmt::Result {
+    write!(f, "{}", what)?;
+    for state in states {
+        write!(f, "\n  {}", state)?;
+    }
+
+    Ok(())
+}
+
+impl Error for NetworkError {}
+
+struct Node {
+    name: String,
+    computer: IntCode,
+    input: String,
+    route: Route,
+    idle: Option<Op>,
+    frame: Vec<Op>,
+    state: State,
+}
+
+/// Machines wired together by named channels, run cooperatively on the
+/// calling thread. Each machine reads from one channel and routes its
+/// output per its `Route`. Channels are created as they're first used.
+pub struct Network {
+    nodes: Vec<Node>,
+    channels: HashMap<String, VecDeque<Op>>,
+    idle_rounds: usize,
+    turn_steps: u64,
+}
+
+impl Network {
+    pub fn new() -> Network {
+        Network {
+            nodes: Vec::new(),
+            channels: HashMap::new(),
+            idle_rounds: DEFAULT_IDLE_ROUNDS,
+            turn_steps: DEFAULT_TURN_STEPS,
+        }
+    }
+
+    /// Each machine reads from a channel named after it and writes to the
+    /// next machine's, the last one wrapping around to the first
+    pub fn ring<N: Into<String>, I: IntoIterator<Item = (N, IntCode)>>(machines: I) -> Network {
+        let mut network = Network::chain(machines);
+        if let Some(first) = network.nodes.first().map(|node| node.name.clone()) {
+            network.nodes.last_mut().unwrap().route = Route::Channel(first);
+        }
+
+        network
+    }
+
+    /// Like a ring, but the last machine writes to `PIPELINE_OUTPUT`
+    pub fn pipeline<N: Into<String>, I: IntoIterator<Item = (N, IntCode)>>(machines: I) -> Network {
+        Network::chain(machines)
+    }
+
+    /// Machines are named and addressed by their index. Output is sent as
+    /// packets, with packets to addresses that aren't machines collecting in
+    /// channels of the same name. A machine with no packets waiting reads
+    /// `idle`, if given, instead of blocking.
+    pub fn bus<I: IntoIterator<Item = IntCode>>(
+        machines: I,
+        payload: usize,
+        idle: Option<Op>,
+    ) -> Network {
+        let mut network = Network::new();
+        for (address, computer) in machines.into_iter().enumerate() {
+            let name = address.to_string();
+            network.add_machine(&name, computer, &name, Route::Packets { payload });
+            network.nodes[address].idle = idle;
+        }
+
+        network
+    }
+
+    fn chain<N: Into<String>, I: IntoIterator<Item = (N, IntCode)>>(machines: I) -> Network {
+        let mut network = Network::new();
+        let machines: Vec<(String, IntCode)> = machines
+            .into_iter()
+            .map(|(name, computer)| (name.into(), computer))
+            .collect();
+        let mut outputs: Vec<String> = machines.iter().skip(1).map(|(n, _)| n.clone()).collect();
+        outputs.push(PIPELINE_OUTPUT.to_string());
+
+        for ((name, computer), output) in machines.into_iter().zip(outputs) {
+            network.add_machine(&name, computer, &name, Route::Channel(output));
+        }
+
+        network
+    }
+
+    /// Adds a machine reading from the `input` channel. Its output only goes
+    /// where `route` says, it isn't also collected in the machine.
+    pub fn add_machine(&mut self, name: &str, mut computer: IntCode, input: &str, route: Route) {
+        computer.set_output_port(NullPort);
+        self.channels.entry(input.to_string()).or_default();
+        self.nodes.push(Node {
+            name: name.to_string(),
+            computer,
+            input: input.to_string(),
+            route,
+            idle: None,
+            frame: Vec::new(),
+            state: State::Ready,
+        });
+    }
+
+    /// Quiet rounds tolerated before giving up with a deadlock
+    pub fn set_idle_rounds(&mut self, rounds: usize) {
+        self.idle_rounds = rounds.max(1);
+    }
+
+    /// Most steps a machine runs in one turn
+    pub fn set_turn_steps(&mut self, steps: u64) {
+        self.turn_steps = steps.max(1);
+    }
+
+    pub fn send(&mut self, channel: &str, value: Op) {
+        self.channels
+            .entry(channel.to_string())
+            .or_default()
+            .push_back(value);
+    }
+
+    /// Takes the oldest value off a channel
+    pub fn receive(&mut self, channel: &str) -> Option<Op> {
+        self.channels.get_mut(channel)?.pop_front()
+    }
+
+    pub fn channel(&self, channel: &str) -> Option<&VecDeque<Op>> {
+        self.channels.get(channel)
+    }
+
+    pub fn machine(&self, name: &str) -> Option<&IntCode> {
+        self.node(name).map(|node| &node.computer)
+    }
+
+    pub fn states(&self) -> Vec<MachineState> {
+        self.nodes
+            .iter()
+            .map(|node| MachineState {
+                name: node.name.clone(),
+                ip: node.computer.ip(),
+                state: node.state.clone(),
+            })
+            .collect()
+    }
+
+    /// Gives each machine a turn in order until every one has halted. A
+    /// turn lasts until the machine halts, its input channel runs dry or
+    /// it has run `set_turn_steps` steps. Steps alone aren't progress, so
+    /// machines that spin without reading or writing end up deadlocked.
+    pub fn run(&mut self) -> Result<(), NetworkError> {
+        let mut quiet = 0;
+        loop {
+            let mut progress = false;
+            for index in 0..self.nodes.len() {
+                progress |= self.turn(index)?;
+            }
+
+            if self.nodes.iter().all(|node| node.state == State::Halted) {
+                return Ok(());
+            }
+
+            quiet = if progress { 0 } else { quiet + 1 };
+            if quiet >= self.idle_rounds {
+                return Err(NetworkError::Deadlock(self.states()));
+            }
+        }
+    }
+
+    /// Runs one machine's turn, returning whether it did anything visible:
+    /// took input off its channel, produced output, or halted
+    fn turn(&mut self, index: usize) -> Result<bool, NetworkError> {
+        let Network {
+            nodes,
+            channels,
+            turn_steps,
+            ..
+        } = self;
+        let node = &mut nodes[index];
+        if node.state == State::Halted {
+            return Ok(false);
+        }
+
+        let mut progress = false;
+        let start = node.computer.stats().steps;
+        loop {
+            let steps = *turn_steps - (node.computer.stats().steps - start);
+            let status =
+                node.computer
+                    .resume_at_most(steps)
+                    .map_err(|error| NetworkError::Fault {
+                        machine: node.name.clone(),
+                        error,
+                    })?;
+
+            match status {
+                Status::Output(value) => {
+                    progress = true;
+                    node.send(channels, value);
+                }
+                Status::NeedsInput => {
+                    let queue = channels.entry(node.input.clone()).or_default();
+                    match queue.pop_front() {
+                        Some(value) => {
+                            progress = true;
+                            node.computer.input(value);
+                        }
+                        None => {
+                            node.state = State::Blocked {
+                                channel: node.input.clone(),
+                            };
+                            if let Some(idle) = node.idle {
+                                node.computer.input(idle);
+                            }
+
+                            return Ok(progress);
+                        }
+                    }
+                }
+                Status::Halted => {
+                    node.state = State::Halted;
+                    return Ok(true);
+                }
+                Status::BudgetExhausted if node.computer.budget() != Some(0) => {
+                    node.state = State::Ready;
+                    return Ok(progress);
+                }
+                Status::BudgetExhausted => {
+                    return Err(NetworkError::OutOfBudget {
+                        machine: node.name.clone(),
+                    })
+                }
+            }
+        }
+    }
+
+    fn node(&self, name: &str) -> Option<&Node> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+}
+
+impl Default for Network {
+    fn default() -> Network {
+        Network::new()
+    }
+}
+
+impl Node {
+    fn send(&mut self, channels: &mut HashMap<String, VecDeque<Op>>, value: Op) {
+        match &self.route {
+            Route::Channel(channel) => channels
+                .entry(channel.clone())
+                .or_default()
+                .push_back(value),
+            Route::Packets { payload } => {
+                self.frame.push(value);
+                if self.frame.len() == payload + 1 {
+                    let channel = channels.entry(self.frame[0].to_string()).or_default();
+                    channel.extend(self.frame.drain(..).skip(1));
+                }
+            }
+            Route::Discard => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Day 7 part 2 example, feedback loop of five amplifiers
+    const AMPLIFIER: &str =
+        "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
+
+    // Doubles every input
+    const DOUBLER: &str = "3,9,1002,9,2,9,4,9,1105,0,0";
+
+    fn machines(program: &str, names: &[&'static str]) -> Vec<(&'static str, IntCode)> {
+        names
+            .iter()
+            .map(|&name| (name, IntCode::new(program).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn ring() {
+        let names = ["A", "B", "C", "D", "E"];
+        let mut network = Network::ring(machines(AMPLIFIER, &names));
+        for (name, &phase) in names.iter().zip([9, 8, 7, 6, 5].iter()) {
+            network.send(name, phase);
+        }
+        network.send("A", 0);
+
+        assert_eq!(network.run(), Ok(()));
+        assert_eq!(network.receive("A"), Some(139_629_729));
+    }
+
+    #[test]
+    fn pipeline() {
+        let mut network = Network::pipeline(machines(DOUBLER, &["x2", "x4", "x8"]));
+        network.send("x2", 1);
+        network.send("x2", 3);
+
+        // Doublers loop forever, so the pipeline ends blocked
+        let states = match network.run() {
+            Err(NetworkError::Deadlock(states)) => states,
+            other => panic!("expected deadlock, got {:?}", other),
+        };
+        assert_eq!(
+            network.channel(PIPELINE_OUTPUT),
+            Some(&vec![8, 24].into_iter().collect())
+        );
+        assert_eq!(states.len(), 3);
+        assert_eq!(
+            states[1],
+            MachineState {
+                name: "x4".to_string(),
+                ip: 0,
+                state: State::Blocked {
+                    channel: "x4".to_string()
+                },
+            }
+        );
+        assert_eq!(states[1].to_string(), "x4 blocked on channel x4 at ip 0");
+    }
+
+    #[test]
+    fn bus() {
+        // Reads its own address, then a value, and sends the value plus one
+        // to the next address. Reads the idle value -1 while it waits.
+        let relay = IntCode::new(
+            "3,100,3,101,1008,101,-1,102,1005,102,2,1001,100,1,103,1001,101,1,104,4,103,4,104,99",
+        )
+        .unwrap();
+        let mut network = Network::bus(vec![relay.clone(), relay.clone(), relay], 1, Some(-1));
+        for address in 0..3 {
+            network.send(&address.to_string(), address);
+        }
+        network.send("0", 10);
+
+        assert_eq!(network.run(), Ok(()));
+        assert_eq!(network.receive("3"), Some(13));
+    }
+
+    #[test]
+    fn idle_bus_deadlocks() {
+        // Polls for input forever without ever sending anything
+        let poller = IntCode::new("3,5,1105,1,0,0").unwrap();
+        let mut network = Network::bus(vec![poller], 1, Some(-1));
+        network.set_idle_rounds(5);
+        match network.run() {
+            Err(NetworkError::Deadlock(states)) => assert_eq!(states.len(), 1),
+            other => panic!("expected deadlock, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fault_names_machine() {
+        let mut network = Network::new();
+        network.add_machine("ok", IntCode::new("99").unwrap(), "in", Route::Discard);
+        network.add_machine("bad", IntCode::new("42").unwrap(), "in", Route::Discard);
+        assert_eq!(
+            network.run(),
+            Err(NetworkError::Fault {
+                machine: "bad".to_string(),
+                error: VmError::UnknownOpcode {
+                    ip: 0,
+                    opcode: 42,
+                    operand: Some(0)
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn out_of_budget() {
+        // Spins forever
+        let mut spinner = IntCode::new("1105,1,0").unwrap();
+        spinner.set_budget(Some(1000));
+        let mut network = Network::new();
+        network.add_machine("spin", spinner, "in", Route::Discard);
+        assert_eq!(
+            network.run(),
+            Err(NetworkError::OutOfBudget {
+                machine: "spin".to_string()
+            })
+        );
+    }
+
+    // Adds up three inputs
+    const SUM3: &str = "3,20,3,21,3,22,1,20,21,20,1,20,22,20,4,20,99";
+
+    #[test]
+    fn turns_are_bounded() {
+        // Sends 1 forever, the sum still gets its turn before the sender
+        // runs out of budget
+        let mut producer = IntCode::new("104,1,1105,1,0").unwrap();
+        producer.set_budget(Some(1000));
+        let mut network = Network::new();
+        network.add_machine("ones", producer, "ones", Route::Channel("sum".into()));
+        network.add_machine(
+            "sum",
+            IntCode::new(SUM3).unwrap(),
+            "sum",
+            Route::Channel(PIPELINE_OUTPUT.into()),
+        );
+        network.set_turn_steps(10);
+
+        assert_eq!(
+            network.run(),
+            Err(NetworkError::OutOfBudget {
+                machine: "ones".to_string()
+            })
+        );
+        assert_eq!(network.receive(PIPELINE_OUTPUT), Some(3));
+    }
+
+    #[test]
+    fn spinning_deadlocks() {
+        // Sends three values, then spins without ever halting
+        let producer = IntCode::new("104,1,104,2,104,3,1105,1,6").unwrap();
+        let mut network = Network::new();
+        network.add_machine("spin", producer, "spin", Route::Channel("sum".into()));
+        network.add_machine(
+            "sum",
+            IntCode::new(SUM3).unwrap(),
+            "sum",
+            Route::Channel(PIPELINE_OUTPUT.into()),
+        );
+
+        match network.run() {
+            Err(NetworkError::Deadlock(states)) => {
+                assert_eq!(states[0].to_string(), "spin ready at ip 6");
+                assert_eq!(states[1].state, State::Halted);
+            }
+            other => panic!("expected deadlock, got {:?}", other),
+        }
+        assert_eq!(network.receive(PIPELINE_OUTPUT), Some(6));
+    }
+}
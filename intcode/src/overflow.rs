@@ -0,0 +1,84 @@
+use crate::Op;
+
+/// What arithmetic does when a result doesn't fit in an `Op`. Applies to
+/// `ADD`, `MUL`, relative base adjustments and relative addressing.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Overflow {
+    Wrapping,
+    /// Fault with `VmError::Overflow`
+    #[default]
+    Checked,
+    Saturating,
+}
+
+impl Overflow {
+    pub(crate) fn add(self, a: Op, b: Op) -> Option<Op> {
+        match self {
+            Overflow::Wrapping => Some(a.wrapping_add(b)),
+            Overflow::Checked => a.checked_add(b),
+            Overflow::Saturating => Some(a.saturating_add(b)),
+        }
+    }
+
+    pub(crate) fn multiply(self, a: Op, b: Op) -> Option<Op> {
+        match self {
+            Overflow::Wrapping => Some(a.wrapping_mul(b)),
+            Overflow::Checked => a.checked_mul(b),
+            Overflow::Saturating => Some(a.saturating_mul(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntCode, Status, VmError};
+
+    // Squares its input
+    const SQUARE: &str = "3,0,2,0,0,0,4,0,99";
+
+    fn square(overflow: Overflow, value: Op) -> crate::Result<Status> {
+        let mut computer = IntCode::new(SQUARE).unwrap();
+        computer.set_overflow(overflow);
+        computer.input(value);
+        computer.resume()
+    }
+
+    #[test]
+    fn policies() {
+        let big = 1 << 32;
+        assert_eq!(
+            square(Overflow::Checked, big),
+            Err(VmError::Overflow {
+                ip: 2,
+                opcode: 2,
+                a: big,
+                b: big
+            })
+        );
+        assert_eq!(square(Overflow::Wrapping, big), Ok(Status::Output(0)));
+        assert_eq!(
+            square(Overflow::Saturating, -big),
+            Ok(Status::Output(Op::MAX))
+        );
+        assert_eq!(square(Overflow::Checked, 3), Ok(Status::Output(9)));
+    }
+
+    #[test]
+    fn relative_base() {
+        let mut computer = IntCode::new("109,9223372036854775807,109,1,99").unwrap();
+        assert_eq!(
+            computer.run(),
+            Err(VmError::Overflow {
+                ip: 2,
+                opcode: 109,
+                a: Op::MAX,
+                b: 1
+            })
+        );
+
+        computer.set_overflow(Overflow::Saturating);
+        assert_eq!(computer.run(), Ok(Status::Halted));
+        assert_eq!(computer.relative_base(), Op::MAX);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{load, parse_program, IntCode, Memory, Op, Ops, Result};
+use std::fmt;
+use std::path::Path;
+
+/// Named cell values to poke into a program before it runs
+#[derive(PartialEq, Debug, Clone)]
+pub struct Patch {
+    name: String,
+    cells: Vec<(usize, Op)>,
+}
+
+impl Patch {
+    pub fn new(name: &str) -> Patch {
+        Patch {
+            name: name.to_string(),
+            cells: Vec::new(),
+        }
+    }
+
+    /// Adds a cell to the patch. Later values for the same address win.
+    pub fn set(mut self, address: usize, value: Op) -> Patch {
+        self.cells.push((address, value));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn cells(&self) -> &[(usize, Op)] {
+        &self.cells
+    }
+}
+
+/// `name: address=value, ...`
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cells: Vec<String> = self
+            .cells
+            .iter()
+            .map(|(address, value)| format!("{}={}", address, value))
+            .collect();
+        write!(f, "{}: {}", self.name, cells.join(", "))
+    }
+}
+
+/// A program loaded once, with any patches applied, to spawn fresh machines
+/// from. Memory is shared copy-on-write between the program, its clones and
+/// every machine spawned from them, so a search that tries thousands of
+/// patched variants only copies the pages each variant writes to.
+///
+/// ```
+/// use intcode::{Patch, Program};
+///
+/// let program = Program::new("1,0,0,0,99").unwrap();
+/// let mut computer = program
+///     .clone()
+///     .patch(Patch::new("double four").set(1, 4).set(2, 4))
+///     .spawn();
+/// computer.run().unwrap();
+/// assert_eq!(computer.memory()[0], 198);
+/// assert_eq!(program.get(0), 1);
+/// ```
+#[derive(PartialEq, Debug, Clone)]
+pub struct Program {
+    memory: Memory,
+    patches: Vec<Patch>,
+}
+
+impl Program {
+    pub fn new(program: &str) -> Result<Program> {
+        Ok(Program::from(parse_program(program)?))
+    }
+
+    /// Reads a program in either the text or binary format
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Program> {
+        Ok(Program::from(load(path)?))
+    }
+
+    /// Applies `patch` on top of any earlier ones. Clone first to keep the
+    /// unpatched program, which is cheap.
+    pub fn patch(mut self, patch: Patch) -> Program {
+        for &(address, value) in patch.cells() {
+            self.memory[address] = value;
+        }
+        self.patches.push(patch);
+        self
+    }
+
+    /// Patches applied so far, in order
+    pub fn patches(&self) -> &[Patch] {
+        &self.patches
+    }
+
+    pub fn get(&self, address: usize) -> Op {
+        self.memory.get(address)
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// A new machine at the start of the patched program
+    pub fn spawn(&self) -> IntCode {
+        IntCode::with_memory(self.memory.clone())
+    }
+}
+
+impl From<Ops> for Program {
+    fn from(image: Ops) -> Program {
+        Program {
+            memory: Memory::new(image),
+            patches: Vec::new(),
+        }
+    }
+}
+
+impl IntCode {
+    /// Pokes `patch` into memory. Meant for setting up a machine before
+    /// it runs, though it works at any point.
+    pub fn patch(&mut self, patch: &Patch) {
+        for &(address, value) in patch.cells() {
+            self.ops[address] = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawns_patched() {
+        let program = Program::new("1,9,10,3,2,3,11,0,99,30,40,50").unwrap();
+        let patched = program
+            .clone()
+            .patch(Patch::new("swap").set(9, 3).set(10, 4));
+        assert_eq!(patched.patches()[0].to_string(), "swap: 9=3, 10=4");
+
+        let mut computer = patched.spawn();
+        computer.run().unwrap();
+        assert_eq!(computer.memory()[0], 7 * 50);
+
+        let mut computer = program.spawn();
+        computer.run().unwrap();
+        assert_eq!(computer.memory()[0], 3500);
+        assert_eq!((patched.get(0), patched.get(9)), (1, 3));
+    }
+
+    #[test]
+    fn patch_running_machine() {
+        let mut computer = IntCode::new("1,0,0,0,99").unwrap();
+        computer.patch(&Patch::new("noun and verb").set(1, 4).set(2, 4));
+        computer.run().unwrap();
+        assert_eq!(computer.memory()[0], 198);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::Op;
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+
+/// Where a machine's input instructions read from once its own input queue
+/// is empty. `None` means nothing is available yet, and the machine reports
+/// `Status::NeedsInput`.
+pub trait InputPort {
+    fn read(&mut self) -> Option<Op>;
+}
+
+/// Where a machine's output instructions write to
+pub trait OutputPort {
+    fn write(&mut self, value: Op);
+}
+
+impl InputPort for VecDeque<Op> {
+    fn read(&mut self) -> Option<Op> {
+        self.pop_front()
+    }
+}
+
+impl OutputPort for VecDeque<Op> {
+    fn write(&mut self, value: Op) {
+        self.push_back(value);
+    }
+}
+
+/// Blocks until a value arrives. Reads `None` once every sender is gone.
+impl InputPort for Receiver<Op> {
+    fn read(&mut self) -> Option<Op> {
+        self.recv().ok()
+    }
+}
+
+/// Values sent after the receiver hung up are dropped
+impl OutputPort for Sender<Op> {
+    fn write(&mut self, value: Op) {
+        let _ = self.send(value);
+    }
+}
+
+impl OutputPort for SyncSender<Op> {
+    fn write(&mut self, value: Op) {
+        let _ = self.send(value);
+    }
+}
+
+/// Input produced on demand by a closure
+pub struct InputFn<F: FnMut() -> Option<Op>>(pub F);
+
+impl<F: FnMut() -> Option<Op>> InputPort for InputFn<F> {
+    fn read(&mut self) -> Option<Op> {
+        (self.0)()
+    }
+}
+
+/// Output handed to a closure as it's produced
+pub struct OutputFn<F: FnMut(Op)>(pub F);
+
+impl<F: FnMut(Op)> OutputPort for OutputFn<F> {
+    fn write(&mut self, value: Op) {
+        (self.0)(value)
+    }
+}
+
+/// Discards output, and never has input
+pub struct NullPort;
+
+impl InputPort for NullPort {
+    fn read(&mut self) -> Option<Op> {
+        None
+    }
+}
+
+impl OutputPort for NullPort {
+    fn write(&mut self, _value: Op) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntCode, Status};
+    use std::sync::{mpsc, Arc, Mutex};
+
+    // Doubles every input
+    const DOUBLER: &str = "3,9,1002,9,2,9,4,9,1105,0,0";
+
+    #[test]
+    fn closures() {
+        let mut source = vec![3, 2, 1];
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+
+        let mut computer = IntCode::new(DOUBLER).unwrap();
+        computer.set_input_port(InputFn(move || source.pop()));
+        computer.set_output_port(OutputFn(move |v| sink.lock().unwrap().push(v)));
+        assert_eq!(computer.run(), Ok(Status::NeedsInput));
+        assert_eq!(*seen.lock().unwrap(), vec![2, 4, 6]);
+        assert!(computer.output().is_empty());
+    }
+
+    #[test]
+    fn queue_drains_before_port() {
+        let mut computer = IntCode::new(DOUBLER).unwrap();
+        computer.input(10);
+        computer.set_input_port(vec![20].into_iter().collect::<VecDeque<Op>>());
+        assert_eq!(computer.resume(), Ok(Status::Output(20)));
+        assert_eq!(computer.resume(), Ok(Status::Output(40)));
+        assert_eq!(computer.resume(), Ok(Status::NeedsInput));
+        computer.input(1);
+        assert_eq!(computer.resume(), Ok(Status::Output(2)));
+    }
+
+    #[test]
+    fn channels_chain_machines() {
+        let (to_first, first_in) = mpsc::channel();
+        let (first_out, second_in) = mpsc::channel();
+        let (second_out, results) = mpsc::channel();
+
+        let mut first = IntCode::new(DOUBLER).unwrap();
+        first.set_input_port(first_in);
+        first.set_output_port(first_out);
+        let mut second = IntCode::new(DOUBLER).unwrap();
+        second.set_input_port(second_in);
+        second.set_output_port(second_out);
+
+        let first = std::thread::spawn(move || first.run());
+        let second = std::thread::spawn(move || second.run());
+        to_first.send(1).unwrap();
+        to_first.send(5).unwrap();
+        drop(to_first);
+
+        // Hanging up reads as no input, so each machine stops in turn
+        assert_eq!(first.join().unwrap(), Ok(Status::NeedsInput));
+        assert_eq!(second.join().unwrap(), Ok(Status::NeedsInput));
+        assert_eq!(results.iter().collect::<Vec<Op>>(), vec![4, 20]);
+    }
+
+    #[test]
+    fn null_port() {
+        let mut computer = IntCode::new(DOUBLER).unwrap();
+        computer.input(3);
+        computer.set_output_port(NullPort);
+        assert_eq!(computer.resume(), Ok(Status::Output(6)));
+        assert!(computer.output().is_empty());
+    }
+}
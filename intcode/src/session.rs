@@ -0,0 +1,419 @@
+use crate::{IntCode, Op, Status, VmError};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+const HEADER: &str = "intcode session";
+
+/// Something the machine did, numbered by how many instructions the
+/// machine had executed since recording started
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Event {
+    Input { step: u64, value: Op },
+    Output { step: u64, value: Op },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Input { step, value } => write!(f, "in {} {}", step, value),
+            Event::Output { step, value } => write!(f, "out {} {}", step, value),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum SessionError {
+    /// The machine faulted, or the session file couldn't be read or written
+    Vm(VmError),
+    /// Line `line` (1 based) of a session file isn't understood
+    Parse { line: usize, text: String },
+    /// Replaying against a machine in a different state than the recording
+    /// started from
+    WrongMachine,
+    /// The machine output `value` where the session has `expected`
+    UnexpectedOutput {
+        step: u64,
+        value: Op,
+        expected: Option<Event>,
+    },
+    /// The machine wanted input where the session has `expected`
+    UnexpectedInput { step: u64, expected: Option<Event> },
+    /// The machine halted before the session ends
+    Halted { step: u64, expected: Option<Event> },
+    /// The session's steps ran out with `expected` still to come
+    Unfinished { step: u64, expected: Event },
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let expected = |event: &Option<Event>| match event {
+            Some(event) => format!("expected {}", event),
+            None => "expected the end of the session".to_string(),
+        };
+
+        match self {
+            SessionError::Vm(error) => write!(f, "{}", error),
+            SessionError::Parse { line, text } => {
+                write!(f, "line {} ({:?}) is invalid", line, text)
+            }
+            SessionError::WrongMachine => write!(f, "session was recorded on a different machine"),
+            SessionError::UnexpectedOutput {
+                step,
+                value,
+                expected: event,
+            } => write!(f, "out {} {}, {}", step, value, expected(event)),
+            SessionError::UnexpectedInput {
+                step,
+                expected: event,
+            } => write!(f, "input wanted at step {}, {}", step, expected(event)),
+            SessionError::Halted {
+                step,
+                expected: event,
+            } => write!(f, "halted at step {}, {}", step, expected(event)),
+            SessionError::Unfinished { step, expected } => {
+                write!(f, "stopped at step {}, expected {}", step, expected)
+            }
+        }
+    }
+}
+
+impl Error for SessionError {}
+
+impl From<VmError> for SessionError {
+    fn from(error: VmError) -> SessionError {
+        SessionError::Vm(error)
+    }
+}
+
+/// Every input a machine consumed and output it produced over a stretch of
+/// execution, recorded with `IntCode::record`. Can be saved and replayed to
+/// check a program still behaves exactly the same way.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Session {
+    /// Fingerprint of the machine when recording started
+    machine: u64,
+    events: Vec<Event>,
+    /// Instructions executed over the whole session
+    steps: u64,
+    /// `Stats::steps` when recording started
+    start: u64,
+}
+
+impl Session {
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub(crate) fn input(&mut self, step: u64, value: Op) {
+        let step = step.saturating_sub(self.start);
+        self.events.push(Event::Input { step, value });
+    }
+
+    pub(crate) fn output(&mut self, step: u64, value: Op) {
+        let step = step.saturating_sub(self.start);
+        self.events.push(Event::Output { step, value });
+    }
+
+    /// Runs `computer` for exactly as many steps as the session lasted,
+    /// feeding it the recorded input as it asks and checking every output
+    /// and when it happened. `computer` has to be in the state the
+    /// recording started from with nothing queued to read, which is checked
+    /// as far as memory and registers go.
+    ///
+    /// Uses the machine's budget, and leaves it unlimited.
+    pub fn replay(&self, computer: &mut IntCode) -> Result<(), SessionError> {
+        if fingerprint(computer) != self.machine {
+            return Err(SessionError::WrongMachine);
+        }
+
+        let start = computer.stats().steps;
+        let mut events = self.events.iter().copied().peekable();
+        loop {
+            computer.set_budget(Some(self.steps - (computer.stats().steps - start)));
+            let status = computer.resume()?;
+            let step = computer.stats().steps - start;
+            match status {
+                Status::Output(value) => {
+                    // Counted already, so this was the previous step
+                    let step = step - 1;
+                    match events.next() {
+                        Some(Event::Output { step: s, value: v }) if (s, v) == (step, value) => {}
+                        expected => {
+                            return Err(SessionError::UnexpectedOutput {
+                                step,
+                                value,
+                                expected,
+                            })
+                        }
+                    }
+                }
+                Status::NeedsInput => match events.peek() {
+                    Some(&Event::Input { step: s, value }) if s == step => {
+                        events.next();
+                        computer.input(value);
+                    }
+                    expected => {
+                        return Err(SessionError::UnexpectedInput {
+                            step,
+                            expected: expected.copied(),
+                        })
+                    }
+                },
+                Status::Halted if step == self.steps && events.peek().is_none() => break,
+                Status::Halted => {
+                    return Err(SessionError::Halted {
+                        step,
+                        expected: events.next(),
+                    })
+                }
+                Status::BudgetExhausted => match events.next() {
+                    Some(expected) => return Err(SessionError::Unfinished { step, expected }),
+                    None => break,
+                },
+            }
+        }
+
+        computer.set_budget(None);
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SessionError> {
+        fs::write(path, self.to_string()).map_err(|error| VmError::from(error).into())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Session, SessionError> {
+        let text = fs::read_to_string(path).map_err(VmError::from)?;
+        Session::parse(&text)
+    }
+
+    /// Reads the text form written by `Display`. Blank lines and lines
+    /// starting with `#` are skipped.
+    pub fn parse(text: &str) -> Result<Session, SessionError> {
+        let mut session = Session {
+            machine: 0,
+            events: Vec::new(),
+            steps: 0,
+            start: 0,
+        };
+
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let invalid = |line: usize, text: &str| SessionError::Parse {
+            line,
+            text: text.to_string(),
+        };
+
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            Some((line, text)) => return Err(invalid(line, text)),
+            None => return Err(invalid(1, "")),
+        }
+
+        let mut seen_machine = false;
+        for (line, text) in lines {
+            let words: Vec<&str> = text.split_whitespace().collect();
+            let parsed = match words[..] {
+                ["machine", hex] => u64::from_str_radix(hex, 16)
+                    .ok()
+                    .map(|machine| session.machine = machine)
+                    .map(|_| seen_machine = true),
+                ["steps", steps] => steps.parse().ok().map(|steps| session.steps = steps),
+                [kind @ "in", step, value] | [kind @ "out", step, value] => step
+                    .parse()
+                    .ok()
+                    .zip(value.parse().ok())
+                    .map(|(step, value)| {
+                        session.events.push(match kind {
+                            "in" => Event::Input { step, value },
+                            _ => Event::Output { step, value },
+                        })
+                    }),
+                _ => None,
+            };
+
+            if parsed.is_none() {
+                return Err(invalid(line, text));
+            }
+        }
+
+        if !seen_machine {
+            return Err(invalid(1, HEADER));
+        }
+
+        Ok(session)
+    }
+}
+
+/// A header naming the machine, one line per event, then the step count
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "machine {:016x}", self.machine)?;
+        for event in self.events.iter() {
+            writeln!(f, "{}", event)?;
+        }
+        writeln!(f, "steps {}", self.steps)
+    }
+}
+
+impl IntCode {
+    /// Starts recording every input consumed and output produced, dropping
+    /// any recording already in progress
+    pub fn record(&mut self) {
+        self.session = Some(Session {
+            machine: fingerprint(self),
+            events: Vec::new(),
+            steps: 0,
+            start: self.stats.steps,
+        });
+    }
+
+    /// Everything recorded up to now
+    pub fn session(&self) -> Option<Session> {
+        self.session.as_ref().map(|session| Session {
+            steps: self.stats.steps.saturating_sub(session.start),
+            ..session.clone()
+        })
+    }
+
+    /// Stops recording, returning what was recorded
+    pub fn take_session(&mut self) -> Option<Session> {
+        let session = self.session();
+        self.session = None;
+        session
+    }
+}
+
+/// FNV-1a over registers and memory
+fn fingerprint(computer: &IntCode) -> u64 {
+    let mut cells = vec![computer.ip() as Op, computer.relative_base()];
+    cells.extend_from_slice(&computer.ops.image());
+    for (address, value) in computer.ops.sparse_cells() {
+        cells.extend_from_slice(&[address as Op, value]);
+    }
+
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for cell in cells {
+        for byte in cell.to_le_bytes().iter() {
+            hash = (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Engine;
+
+    // Echoes input doubled until it reads a zero
+    const DOUBLER: &str = "3,15,1006,15,14,1002,15,2,15,4,15,1105,1,0,99,0";
+
+    fn recorded() -> Session {
+        let mut computer = IntCode::new(DOUBLER).unwrap();
+        computer.record();
+        for &value in [4, 7, 0].iter() {
+            computer.input(value);
+            computer.run().unwrap();
+        }
+        computer.take_session().unwrap()
+    }
+
+    #[test]
+    fn records_steps() {
+        let session = recorded();
+        assert_eq!(
+            session.events(),
+            &[
+                Event::Input { step: 0, value: 4 },
+                Event::Output { step: 3, value: 8 },
+                Event::Input { step: 5, value: 7 },
+                Event::Output { step: 8, value: 14 },
+                Event::Input { step: 10, value: 0 },
+            ]
+        );
+        assert_eq!(session.steps(), 12);
+    }
+
+    #[test]
+    fn replays() {
+        let session = recorded();
+        for &engine in [Engine::Reference, Engine::Cached].iter() {
+            let mut computer = IntCode::new(DOUBLER).unwrap();
+            computer.set_engine(engine);
+            assert_eq!(session.replay(&mut computer), Ok(()));
+            assert_eq!(computer.output(), &vec![8, 14]);
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let session = recorded();
+        let text = session.to_string();
+        assert!(text.starts_with("intcode session\nmachine "));
+        assert!(text.ends_with("in 10 0\nsteps 12\n"));
+        assert_eq!(Session::parse(&text), Ok(session));
+
+        assert_eq!(
+            Session::parse("intcode session\nmachine 1\nin 1\n"),
+            Err(SessionError::Parse {
+                line: 3,
+                text: "in 1".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn detects_divergence() {
+        let session = recorded();
+
+        // Triples instead of doubling
+        let mut computer = IntCode::new("3,15,1006,15,14,1002,15,3,15,4,15,1105,1,0,99,0").unwrap();
+        assert_eq!(
+            session.replay(&mut computer),
+            Err(SessionError::WrongMachine)
+        );
+
+        let text = session.to_string().replace("out 8 14", "out 8 15");
+        let mut computer = IntCode::new(DOUBLER).unwrap();
+        assert_eq!(
+            Session::parse(&text).unwrap().replay(&mut computer),
+            Err(SessionError::UnexpectedOutput {
+                step: 8,
+                value: 14,
+                expected: Some(Event::Output { step: 8, value: 15 })
+            })
+        );
+
+        let text = session.to_string().replace("steps 12", "steps 20");
+        let mut computer = IntCode::new(DOUBLER).unwrap();
+        assert_eq!(
+            Session::parse(&text).unwrap().replay(&mut computer),
+            Err(SessionError::Halted {
+                step: 12,
+                expected: None
+            })
+        );
+
+        let text = session.to_string().replace("steps 12", "steps 8");
+        let mut computer = IntCode::new(DOUBLER).unwrap();
+        let error = Session::parse(&text).unwrap().replay(&mut computer);
+        assert_eq!(
+            error,
+            Err(SessionError::Unfinished {
+                step: 8,
+                expected: Event::Output { step: 8, value: 14 }
+            })
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{IntCode, Memory, Op, Result, VmError};
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+const MAGIC: &[u8; 4] = b"ICSN";
+const VERSION: u8 = 1;
+
+/// Full machine state, taken with `IntCode::snapshot`. Can be restored
+/// into any machine, or saved with `to_bytes` and loaded back later.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub(crate) memory: Memory,
+    pub(crate) ip: usize,
+    pub(crate) relative_base: Op,
+    pub(crate) input: Vec<Op>,
+    pub(crate) input_pos: usize,
+    pub(crate) output: Vec<Op>,
+}
+
+impl Snapshot {
+    /// Serializes as `ICSN`, a version byte, then little endian 64 bit
+    /// fields: ip, relative base, input cursor, input, output, memory limit,
+    /// image and sparse cells. Sequences are length prefixed.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+
+        let mut put = |value: u64| bytes.extend_from_slice(&value.to_le_bytes());
+        put(self.ip as u64);
+        put(self.relative_base as u64);
+        put(self.input_pos as u64);
+        for ops in [&self.input[..], &self.output[..], &self.memory.image()[..]].iter() {
+            put(ops.len() as u64);
+            for &op in ops.iter() {
+                put(op as u64);
+            }
+        }
+
+        put(self.memory.limit() as u64);
+        let sparse = self.memory.sparse_cells();
+        put(sparse.len() as u64);
+        for (address, value) in sparse {
+            put(address as u64);
+            put(value as u64);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot> {
+        if bytes.len() < 5 || &bytes[..4] != MAGIC {
+            return Err(corrupt(0, "not a snapshot"));
+        }
+
+        if bytes[4] != VERSION {
+            return Err(corrupt(4, "unsupported snapshot version"));
+        }
+
+        let mut reader = Reader { bytes, offset: 5 };
+        let ip = reader.size()?;
+        let relative_base = reader.op()?;
+        let input_pos = reader.size()?;
+        let input = reader.ops()?;
+        let output = reader.ops()?;
+        let image = reader.ops()?;
+        let limit = reader.size()?;
+
+        let mut sparse = HashMap::new();
+        for _ in 0..reader.size()? {
+            let offset = reader.offset;
+            let address = reader.size()?;
+            if address < image.len() {
+                return Err(corrupt(offset, "sparse cell inside the image"));
+            }
+            sparse.insert(address, reader.op()?);
+        }
+
+        if reader.offset != bytes.len() {
+            return Err(corrupt(reader.offset, "trailing bytes"));
+        }
+
+        if input_pos > input.len() {
+            return Err(corrupt(5, "input cursor past end of input"));
+        }
+
+        Ok(Snapshot {
+            memory: Memory::from_parts(image, sparse, limit),
+            ip,
+            relative_base,
+            input,
+            input_pos,
+            output,
+        })
+    }
+}
+
+impl From<Snapshot> for IntCode {
+    fn from(snapshot: Snapshot) -> IntCode {
+        let mut computer = IntCode::init(Vec::new());
+        computer.restore(&snapshot);
+        computer
+    }
+}
+
+fn corrupt(offset: usize, reason: &'static str) -> VmError {
+    VmError::Corrupt { offset, reason }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn word(&mut self) -> Result<u64> {
+        let end = self.offset + 8;
+        let chunk = self
+            .bytes
+            .get(self.offset..end)
+            .ok_or_else(|| corrupt(self.offset, "unexpected end of snapshot"))?;
+        self.offset = end;
+        Ok(u64::from_le_bytes(chunk.try_into().unwrap()))
+    }
+
+    fn op(&mut self) -> Result<Op> {
+        Ok(self.word()? as Op)
+    }
+
+    fn size(&mut self) -> Result<usize> {
+        let offset = self.offset;
+        let word = self.word()?;
+        word.try_into()
+            .ok()
+            .filter(|&size: &usize| size <= isize::MAX as usize)
+            .ok_or_else(|| corrupt(offset, "size out of range"))
+    }
+
+    fn ops(&mut self) -> Result<Vec<Op>> {
+        let offset = self.offset;
+        let len = self.size()?;
+        if len > (self.bytes.len() - self.offset) / 8 {
+            return Err(corrupt(offset, "length runs past end of snapshot"));
+        }
+
+        (0..len).map(|_| self.op()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Status;
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut computer = IntCode::new("3,0,109,7,21101,2,3,100000,4,100007,99").unwrap();
+        computer.input(42);
+        computer.input(43);
+        assert_eq!(computer.resume(), Ok(Status::Output(5)));
+
+        let snapshot = computer.snapshot();
+        let bytes = snapshot.to_bytes();
+        assert_eq!(Snapshot::from_bytes(&bytes), Ok(snapshot));
+    }
+
+    #[test]
+    fn corrupt_bytes() {
+        let computer = IntCode::new("104,1,99").unwrap();
+        let bytes = computer.snapshot().to_bytes();
+
+        assert!(Snapshot::from_bytes(b"nope").is_err());
+        assert_eq!(
+            Snapshot::from_bytes(&bytes[..bytes.len() - 3]),
+            Err(VmError::Corrupt {
+                offset: bytes.len() - 8,
+                reason: "unexpected end of snapshot"
+            })
+        );
+
+        let mut long = bytes.clone();
+        long.push(0);
+        assert!(Snapshot::from_bytes(&long).is_err());
+
+        // One sparse cell, at address 1 where the image already is
+        let mut overlapping = bytes[..bytes.len() - 8].to_vec();
+        for word in [1u64, 1, 5].iter() {
+            overlapping.extend_from_slice(&word.to_le_bytes());
+        }
+        assert_eq!(
+            Snapshot::from_bytes(&overlapping),
+            Err(VmError::Corrupt {
+                offset: bytes.len(),
+                reason: "sparse cell inside the image"
+            })
+        );
+    }
+}
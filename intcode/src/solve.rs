@@ -0,0 +1,203 @@
+use crate::{End, Linear, Op, Ops, Program, Status, Var};
+use std::ops::Range;
+
+/// Instructions each attempt may take before it's given up on, whether
+/// it's run symbolically or for real
+const SOLVE_BUDGET: u64 = 1_000_000;
+
+/// Every assignment of the unknown cells that leaves the target at
+/// address 0, found by `Program::solve`
+#[derive(PartialEq, Debug, Clone)]
+pub struct Solutions {
+    /// Values for the cells in the order they were given, sorted
+    pub values: Vec<Ops>,
+    /// The formula the solutions were worked out from, `None` if every
+    /// combination had to be run
+    pub formula: Option<Linear>,
+}
+
+impl Program {
+    /// Finds every set of values for `cells`, each within its range, that
+    /// leaves `target` at address 0 once the patched program halts.
+    /// Combinations that fault, want input or run too long don't count.
+    ///
+    /// The program is first run with the cells symbolic. If it halts
+    /// without branching on them, with address 0 a linear function of
+    /// them, candidates are solved for directly and each one checked by
+    /// running it. Otherwise every combination is tried.
+    pub fn solve(&self, cells: &[(usize, Range<Op>)], target: Op) -> Solutions {
+        let addresses: Vec<usize> = cells.iter().map(|(address, _)| *address).collect();
+        let ranges: Vec<Range<Op>> = cells.iter().map(|(_, range)| range.clone()).collect();
+
+        let formula = self.formula(&addresses);
+        let values = match &formula {
+            Some(formula) => solve_linear(formula, &addresses, &ranges, target)
+                .into_iter()
+                .filter(|values| self.reaches(&addresses, values, target))
+                .collect(),
+            None => combinations(&ranges)
+                .filter(|values| self.reaches(&addresses, values, target))
+                .collect(),
+        };
+
+        Solutions { values, formula }
+    }
+
+    /// Address 0 at the end of the program, if it gets there without
+    /// branching on the cells
+    fn formula(&self, addresses: &[usize]) -> Option<Linear> {
+        let symbolic = addresses
+            .iter()
+            .fold(self.symbolic(), |symbolic, &address| {
+                symbolic.symbol(address)
+            });
+        let path = symbolic.without_input().budget(SOLVE_BUDGET).run();
+        match path.end {
+            End::Halted => path.cell(0).linear(),
+            _ => None,
+        }
+    }
+
+    /// Whether running with `values` in `addresses` halts with `target`
+    /// at address 0
+    fn reaches(&self, addresses: &[usize], values: &[Op], target: Op) -> bool {
+        let mut computer = self.spawn();
+        for (&address, &value) in addresses.iter().zip(values.iter()) {
+            computer.ops[address] = value;
+        }
+
+        computer.set_budget(Some(SOLVE_BUDGET));
+        computer.run() == Ok(Status::Halted) && computer.ops.get(0) == target
+    }
+}
+
+/// Every combination of values from `ranges`, the last varying fastest.
+/// Counted out like an odometer, so they're made one at a time rather
+/// than all up front.
+fn combinations(ranges: &[Range<Op>]) -> Combinations {
+    let empty = ranges.iter().any(|range| range.is_empty());
+    Combinations {
+        ranges: ranges.to_vec(),
+        next: Some(ranges.iter().map(|range| range.start).collect()).filter(|_| !empty),
+    }
+}
+
+struct Combinations {
+    ranges: Vec<Range<Op>>,
+    next: Option<Ops>,
+}
+
+impl Iterator for Combinations {
+    type Item = Ops;
+
+    fn next(&mut self) -> Option<Ops> {
+        let values = self.next.take()?;
+        let mut next = values.clone();
+        for (value, range) in next.iter_mut().zip(self.ranges.iter()).rev() {
+            *value += 1;
+            if *value < range.end {
+                self.next = Some(next);
+                break;
+            }
+            *value = range.start;
+        }
+
+        Some(values)
+    }
+}
+
+/// Tries every value for all but the last cell, and solves for the last
+fn solve_linear(
+    formula: &Linear,
+    addresses: &[usize],
+    ranges: &[Range<Op>],
+    target: Op,
+) -> Vec<Ops> {
+    let coefficients: Vec<i128> = (addresses.iter())
+        .map(|&address| i128::from(formula.coefficient(Var::Cell(address))))
+        .collect();
+    let (last, rest) = match ranges.split_last() {
+        Some(split) => split,
+        None if formula.constant == target => return vec![Vec::new()],
+        None => return Vec::new(),
+    };
+    let coefficient = coefficients[rest.len()];
+
+    let mut solutions = Vec::new();
+    for mut values in combinations(rest) {
+        let partial: i128 = (coefficients.iter())
+            .zip(values.iter())
+            .map(|(&c, &value)| c * i128::from(value))
+            .sum();
+        let remainder = i128::from(target) - i128::from(formula.constant) - partial;
+
+        if coefficient == 0 {
+            if remainder == 0 {
+                for value in last.clone() {
+                    values.push(value);
+                    solutions.push(values.clone());
+                    values.pop();
+                }
+            }
+        } else if remainder % coefficient == 0 {
+            let value = remainder / coefficient;
+            if value >= i128::from(last.start) && value < i128::from(last.end) {
+                values.push(value as Op);
+                solutions.push(values);
+            }
+        }
+    }
+
+    solutions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Shaped like day 2: the first instruction reads through both unknowns
+    // but its result is overwritten, leaving [0] = 6*[1] + 5*[2]
+    const PROGRAM: &str = "1,0,0,3,1,1,2,3,2,3,17,0,1,0,1,0,99,5";
+
+    #[test]
+    fn linear_program() {
+        let program = Program::new(PROGRAM).unwrap();
+        let solutions = program.solve(&[(1, 0..30), (2, 0..30)], 100);
+        let brute_force: Vec<Ops> = combinations(&[0..30, 0..30])
+            .filter(|values| program.reaches(&[1, 2], values, 100))
+            .collect();
+        assert_eq!(solutions.values, brute_force);
+        assert_eq!(
+            solutions.values,
+            vec![vec![0, 20], vec![5, 14], vec![10, 8], vec![15, 2]]
+        );
+        assert_eq!(solutions.formula.unwrap().to_string(), "0 + 6*[1] + 5*[2]");
+    }
+
+    #[test]
+    fn combinations_count_up() {
+        let all: Vec<Ops> = combinations(&[0..2, 5..7]).collect();
+        assert_eq!(all, vec![vec![0, 5], vec![0, 6], vec![1, 5], vec![1, 6]]);
+        assert_eq!(combinations(&[0..2, 0..0]).next(), None);
+
+        // A billion combinations, only made as they're asked for
+        let mut huge = combinations(&[0..1000, 0..1000, 0..1000]);
+        assert_eq!(huge.nth(1_001), Some(vec![0, 1, 1]));
+    }
+
+    #[test]
+    fn comparison_falls_back() {
+        // Address 0 is 1 if [5] is 7
+        let program = Program::new("1008,5,7,0,99,0").unwrap();
+        let solutions = program.solve(&[(5, 0..20)], 1);
+        assert_eq!(solutions.formula, None);
+        assert_eq!(solutions.values, vec![vec![7]]);
+    }
+
+    #[test]
+    fn no_unknowns() {
+        let program = Program::new("1101,2,3,0,99").unwrap();
+        assert_eq!(program.solve(&[], 5).values, vec![Vec::<Op>::new()]);
+        assert!(program.solve(&[], 6).values.is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{Instruction, Op};
+use std::fmt;
+
+/// Opcodes that can execute, in opcode order. Halting doesn't count as
+/// executing an instruction, it never moves the machine forward.
+const COUNTED: [&str; 9] = ["add", "mul", "in", "out", "jt", "jf", "lt", "eq", "arb"];
+
+/// Running totals of what a machine has done, see `IntCode::stats`
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Stats {
+    /// Instructions executed. Blocking on input, halting and faulting
+    /// don't count.
+    pub steps: u64,
+    by_opcode: [u64; 9],
+    /// One past the highest address the program has written to, or the
+    /// image size if that's larger
+    pub memory_high_water: usize,
+    /// Values consumed by input instructions
+    pub inputs: u64,
+    pub outputs: u64,
+    /// Conditional jumps whose condition held
+    pub jumps: u64,
+}
+
+impl Stats {
+    pub(crate) fn new(image_len: usize) -> Stats {
+        Stats {
+            memory_high_water: image_len,
+            ..Stats::default()
+        }
+    }
+
+    /// How many times instructions with `opcode` have executed
+    pub fn executed(&self, opcode: Op) -> u64 {
+        match opcode {
+            1..=9 => self.by_opcode[opcode as usize - 1],
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn count(&mut self, instruction: &Instruction) {
+        let index = match instruction {
+            Instruction::Add(..) => 0,
+            Instruction::Multiply(..) => 1,
+            Instruction::Input(..) => 2,
+            Instruction::Output(..) => 3,
+            Instruction::JumpIfTrue(..) => 4,
+            Instruction::JumpIfFalse(..) => 5,
+            Instruction::LessThan(..) => 6,
+            Instruction::Equals(..) => 7,
+            Instruction::AdjustRelativeBase(..) => 8,
+            Instruction::Halt | Instruction::Data(_) => return,
+        };
+
+        self.steps += 1;
+        self.by_opcode[index] += 1;
+    }
+
+    pub(crate) fn wrote(&mut self, address: usize) {
+        if address >= self.memory_high_water {
+            self.memory_high_water = address + 1;
+        }
+    }
+}
+
+/// One line of totals, then a count for every opcode that ran
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} steps, {} inputs, {} outputs, {} jumps, memory high water {}",
+            self.steps, self.inputs, self.outputs, self.jumps, self.memory_high_water
+        )?;
+        for (name, &count) in COUNTED.iter().zip(self.by_opcode.iter()) {
+            if count > 0 {
+                write!(f, "\n{:>5} {}", name, count)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, IntCode, Status};
+
+    // Counts down from the input, outputting each value
+    const COUNTDOWN: &str = "3,13,4,13,1001,13,-1,13,1005,13,2,99,0,0";
+
+    fn countdown(engine: Engine) -> IntCode {
+        let mut computer = IntCode::new(COUNTDOWN).unwrap();
+        computer.set_engine(engine);
+        computer.input(3);
+        computer
+    }
+
+    #[test]
+    fn counters() {
+        for &engine in [Engine::Reference, Engine::Cached].iter() {
+            let mut computer = countdown(engine);
+            assert_eq!(computer.run(), Ok(Status::Halted));
+            let stats = computer.stats();
+            assert_eq!(stats.steps, 10);
+            assert_eq!(stats.executed(3), 1);
+            assert_eq!(stats.executed(4), 3);
+            assert_eq!(stats.executed(1), 3);
+            assert_eq!(stats.executed(5), 3);
+            assert_eq!(stats.executed(99), 0);
+            assert_eq!((stats.inputs, stats.outputs, stats.jumps), (1, 3, 2));
+            assert_eq!(stats.memory_high_water, 14);
+        }
+    }
+
+    #[test]
+    fn high_water_past_image() {
+        let mut computer = IntCode::new("1101,1,1,5000,99").unwrap();
+        computer.run().unwrap();
+        assert_eq!(computer.stats().memory_high_water, 5001);
+    }
+
+    #[test]
+    fn budget_resumes() {
+        for &engine in [Engine::Reference, Engine::Cached].iter() {
+            let mut computer = countdown(engine);
+            computer.set_budget(Some(4));
+            assert_eq!(computer.resume(), Ok(Status::Output(3)));
+            assert_eq!(computer.run(), Ok(Status::BudgetExhausted));
+            assert_eq!(computer.ip(), 2);
+            assert_eq!(computer.budget(), Some(0));
+            assert_eq!(computer.stats().steps, 4);
+
+            // Exhausted stays exhausted until there's more budget
+            assert_eq!(computer.resume(), Ok(Status::BudgetExhausted));
+            computer.set_budget(Some(100));
+            assert_eq!(computer.run(), Ok(Status::Halted));
+            assert_eq!(computer.output(), &vec![3, 2, 1]);
+            assert_eq!(computer.budget(), Some(94));
+        }
+    }
+
+    #[test]
+    fn display() {
+        let mut computer = countdown(Engine::Reference);
+        computer.run().unwrap();
+        assert_eq!(
+            computer.stats().to_string(),
+            "10 steps, 1 inputs, 3 outputs, 2 jumps, memory high water 14\n  \
+             add 3\n   in 1\n  out 3\n   jt 3"
+        );
+    }
+}
@@ -0,0 +1,848 @@
+//! Symbolic execution. Input and chosen memory cells are variables, and
+//! arithmetic on them builds expressions instead of numbers. A jump on a
+//! symbolic condition forks the run in two, each side remembering which way
+//! the condition went, so exploring a program gives every path through it
+//! along with the constraints on the variables that lead down that path:
+//!
+//! ```
+//! use intcode::Program;
+//!
+//! // Outputs 999 below 8, 1000 for 8 and 1001 above
+//! let program = Program::new(
+//!     "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,\
+//!      1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,\
+//!      1105,1,46,98,99",
+//! )
+//! .unwrap();
+//!
+//! // Which inputs reach the instruction that outputs 999
+//! let paths = program.symbolic().reaching(31);
+//! assert_eq!(paths.len(), 1);
+//! let constraints: Vec<String> = paths[0].constraints.iter().map(|c| c.to_string()).collect();
+//! assert_eq!(constraints, vec!["in0 != 8", "8 >= in0"]);
+//! ```
+//!
+//! Only control flow and where writes land have to stay concrete. Reading
+//! through a symbolic address gives an unknown value that's fine to carry
+//! around but not to branch on.
+
+use crate::{Instruction, Memory, Op, Param, Program, VmError};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+/// A variable in symbolic expressions
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub enum Var {
+    /// The nth value read by an input instruction, from 0
+    Input(usize),
+    /// What a cell made symbolic held at the start
+    Cell(usize),
+}
+
+/// `in0` for input, `[address]` for cells
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Var::Input(n) => write!(f, "in{}", n),
+            Var::Cell(address) => write!(f, "[{}]", address),
+        }
+    }
+}
+
+/// Values for variables
+pub type Assignment = BTreeMap<Var, Op>;
+
+/// A value computed from variables. Comparisons are 1 or 0 like the
+/// instructions that make them.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Expr {
+    Const(Op),
+    Var(Var),
+    /// Read through an address that depends on the variables
+    Unknown,
+    Add(Box<Expr>, Box<Expr>),
+    Multiply(Box<Expr>, Box<Expr>),
+    LessThan(Box<Expr>, Box<Expr>),
+    Equals(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// `a + b`, folding constants and dropping zeros
+    pub fn sum(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) if a.checked_add(b).is_some() => Expr::Const(a + b),
+            (Expr::Const(0), other) | (other, Expr::Const(0)) => other,
+            (a, b) => Expr::Add(Box::new(a), Box::new(b)),
+        }
+    }
+
+    /// `a * b`, folding constants and multiplications by 0 and 1
+    pub fn product(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) if a.checked_mul(b).is_some() => Expr::Const(a * b),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) => Expr::Const(0),
+            (Expr::Const(1), other) | (other, Expr::Const(1)) => other,
+            (a, b) => Expr::Multiply(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn less_than(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a < b) as Op),
+            (a, b) => Expr::LessThan(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn equals(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a == b) as Op),
+            (a, b) => Expr::Equals(Box::new(a), Box::new(b)),
+        }
+    }
+
+    /// The value for `values`, `None` if a variable is missing, the
+    /// expression has an unknown in it, or the arithmetic overflows
+    pub fn eval(&self, values: &Assignment) -> Option<Op> {
+        match self {
+            Expr::Const(value) => Some(*value),
+            Expr::Var(var) => values.get(var).copied(),
+            Expr::Unknown => None,
+            Expr::Add(a, b) => a.eval(values)?.checked_add(b.eval(values)?),
+            Expr::Multiply(a, b) => a.eval(values)?.checked_mul(b.eval(values)?),
+            Expr::LessThan(a, b) => Some((a.eval(values)? < b.eval(values)?) as Op),
+            Expr::Equals(a, b) => Some((a.eval(values)? == b.eval(values)?) as Op),
+        }
+    }
+
+    pub fn vars(&self) -> BTreeSet<Var> {
+        let mut vars = BTreeSet::new();
+        self.visit(&mut |expr| {
+            if let Expr::Var(var) = expr {
+                vars.insert(*var);
+            }
+        });
+        vars
+    }
+
+    /// The expression as a sum of variables times constants, if it is one
+    pub fn linear(&self) -> Option<Linear> {
+        fn terms(expr: &Expr) -> Option<(Op, BTreeMap<Var, Op>)> {
+            match expr {
+                Expr::Const(value) => Some((*value, BTreeMap::new())),
+                Expr::Var(var) => Some((0, vec![(*var, 1)].into_iter().collect())),
+                Expr::Add(a, b) => {
+                    let (mut constant, mut sum) = terms(a)?;
+                    let (other, more) = terms(b)?;
+                    constant = constant.checked_add(other)?;
+                    for (var, coefficient) in more {
+                        let term = sum.entry(var).or_insert(0);
+                        *term = term.checked_add(coefficient)?;
+                    }
+                    Some((constant, sum))
+                }
+                Expr::Multiply(a, b) => {
+                    let (factor, (constant, sum)) = match (terms(a)?, terms(b)?) {
+                        ((factor, none), other) | (other, (factor, none)) if none.is_empty() => {
+                            (factor, other)
+                        }
+                        _ => return None,
+                    };
+                    let mut scaled = BTreeMap::new();
+                    for (var, coefficient) in sum {
+                        scaled.insert(var, coefficient.checked_mul(factor)?);
+                    }
+                    Some((constant.checked_mul(factor)?, scaled))
+                }
+                Expr::Unknown | Expr::LessThan(..) | Expr::Equals(..) => None,
+            }
+        }
+
+        let (constant, terms) = terms(self)?;
+        Some(Linear {
+            constant,
+            terms: terms.into_iter().filter(|&(_, c)| c != 0).collect(),
+        })
+    }
+
+    fn has_unknown(&self) -> bool {
+        let mut unknown = false;
+        self.visit(&mut |expr| unknown |= *expr == Expr::Unknown);
+        unknown
+    }
+
+    fn visit<F: FnMut(&Expr)>(&self, f: &mut F) {
+        f(self);
+        match self {
+            Expr::Add(a, b) | Expr::Multiply(a, b) | Expr::LessThan(a, b) | Expr::Equals(a, b) => {
+                a.visit(f);
+                b.visit(f);
+            }
+            Expr::Const(_) | Expr::Var(_) | Expr::Unknown => {}
+        }
+    }
+
+    fn fmt_nested(&self, f: &mut fmt::Formatter, nested: bool) -> fmt::Result {
+        let (a, b, operator) = match self {
+            Expr::Const(value) => return write!(f, "{}", value),
+            Expr::Var(var) => return write!(f, "{}", var),
+            Expr::Unknown => return write!(f, "?"),
+            Expr::Add(a, b) => (a, b, "+"),
+            Expr::Multiply(a, b) => (a, b, "*"),
+            Expr::LessThan(a, b) => (a, b, "<"),
+            Expr::Equals(a, b) => (a, b, "=="),
+        };
+
+        if nested {
+            write!(f, "(")?;
+        }
+        a.fmt_nested(f, true)?;
+        write!(f, " {} ", operator)?;
+        b.fmt_nested(f, true)?;
+        if nested {
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Infix, bracketing everything below the top
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_nested(f, false)
+    }
+}
+
+/// A sum of variables times constants
+#[derive(PartialEq, Debug, Clone)]
+pub struct Linear {
+    pub constant: Op,
+    /// Nonzero coefficients, in variable order
+    pub terms: Vec<(Var, Op)>,
+}
+
+impl Linear {
+    pub fn coefficient(&self, var: Var) -> Op {
+        self.terms
+            .iter()
+            .find(|(v, _)| *v == var)
+            .map_or(0, |&(_, coefficient)| coefficient)
+    }
+}
+
+/// `constant + coefficient*var ...`
+impl fmt::Display for Linear {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.constant)?;
+        for (var, coefficient) in self.terms.iter() {
+            write!(f, " + {}*{}", coefficient, var)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Which way a condition went on a path, `holds` meaning it was nonzero
+#[derive(PartialEq, Debug, Clone)]
+pub struct Constraint {
+    pub condition: Expr,
+    pub holds: bool,
+}
+
+impl Constraint {
+    pub fn is_met(&self, values: &Assignment) -> bool {
+        self.condition.eval(values).map(|value| value != 0) == Some(self.holds)
+    }
+}
+
+/// Comparisons read as themselves or their opposite, anything else as
+/// compared to zero
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.condition, self.holds) {
+            (Expr::LessThan(a, b), true) => write!(f, "{} < {}", a, b),
+            (Expr::LessThan(a, b), false) => write!(f, "{} >= {}", a, b),
+            (Expr::Equals(a, b), true) => write!(f, "{} == {}", a, b),
+            (Expr::Equals(a, b), false) => write!(f, "{} != {}", a, b),
+            (condition, true) => write!(f, "{} != 0", condition),
+            (condition, false) => write!(f, "{} == 0", condition),
+        }
+    }
+}
+
+/// How a path finished
+#[derive(PartialEq, Debug, Clone)]
+pub enum End {
+    Halted,
+    /// Wanted input with symbolic input turned off
+    NeedsInput,
+    /// Faulted whatever the variables are
+    Fault(VmError),
+    /// Needed a concrete value at `ip` and had an expression: an opcode,
+    /// a jump target, where to write, or a condition it couldn't fork on
+    Symbolic {
+        ip: usize,
+    },
+    /// The step budget ran out before the path finished
+    OutOfSteps,
+}
+
+/// One way through a program
+#[derive(PartialEq, Debug, Clone)]
+pub struct Path {
+    /// Every branch taken on a symbolic condition, in order, and that any
+    /// symbolic address read wasn't negative
+    pub constraints: Vec<Constraint>,
+    /// Each output with the ip of the instruction that made it
+    pub outputs: Vec<(usize, Expr)>,
+    /// Input values read, `Var::Input(0)` up to this
+    pub inputs: usize,
+    pub end: End,
+    symbols: Vec<usize>,
+    memory: Memory,
+    /// Cells that differ from `memory`
+    cells: HashMap<usize, Expr>,
+}
+
+impl Path {
+    /// A cell as it was when the path ended
+    pub fn cell(&self, address: usize) -> Expr {
+        read(&self.memory, &self.cells, address)
+    }
+
+    /// Whether the output instruction at `ip` ran on this path
+    pub fn reaches(&self, ip: usize) -> bool {
+        self.outputs.iter().any(|&(output, _)| output == ip)
+    }
+
+    /// Every variable the path involves, the symbolic cells then the input
+    pub fn vars(&self) -> Vec<Var> {
+        let cells = self.symbols.iter().map(|&address| Var::Cell(address));
+        cells.chain((0..self.inputs).map(Var::Input)).collect()
+    }
+
+    /// Values for the variables that take the program down this path, if
+    /// one turns up. Only tries values around the constants the
+    /// constraints mention, which is enough for comparisons against
+    /// constants but can miss solutions to anything more involved.
+    pub fn example(&self) -> Option<Assignment> {
+        let mut candidates: BTreeSet<Op> = vec![0].into_iter().collect();
+        for constraint in self.constraints.iter() {
+            constraint.condition.visit(&mut |expr| {
+                if let Expr::Const(value) = expr {
+                    candidates.extend(&[value.saturating_sub(1), *value, value.saturating_add(1)]);
+                }
+            });
+        }
+
+        let vars = self.vars();
+        let candidates: Vec<Op> = candidates.into_iter().collect();
+        let combinations = (candidates.len() as u64).checked_pow(vars.len() as u32);
+        match combinations {
+            Some(combinations) if combinations <= MAX_EXAMPLES => {}
+            _ => return None,
+        }
+
+        let mut choice = vec![0; vars.len()];
+        loop {
+            let values: Assignment = (vars.iter().copied())
+                .zip(choice.iter().map(|&i| candidates[i]))
+                .collect();
+            if self.constraints.iter().all(|c| c.is_met(&values)) {
+                return Some(values);
+            }
+
+            // Count through the candidates like an odometer
+            let mut digit = 0;
+            loop {
+                if digit == choice.len() {
+                    return None;
+                }
+                choice[digit] += 1;
+                if choice[digit] < candidates.len() {
+                    break;
+                }
+                choice[digit] = 0;
+                digit += 1;
+            }
+        }
+    }
+}
+
+/// Combinations of candidate values `Path::example` will try
+const MAX_EXAMPLES: u64 = 1_000_000;
+
+/// Default for `Symbolic::budget`
+const DEFAULT_BUDGET: u64 = 100_000;
+
+/// Settings for exploring a program symbolically, from `Program::symbolic`
+#[derive(Debug, Clone)]
+pub struct Symbolic {
+    memory: Memory,
+    symbols: Vec<usize>,
+    input: bool,
+    budget: u64,
+}
+
+impl Program {
+    /// Explores the program with every input symbolic and no cells
+    pub fn symbolic(&self) -> Symbolic {
+        Symbolic {
+            memory: self.memory().clone(),
+            symbols: Vec::new(),
+            input: true,
+            budget: DEFAULT_BUDGET,
+        }
+    }
+}
+
+/// Execution state for one path that's still going
+#[derive(Clone, Default)]
+struct State {
+    ip: usize,
+    relative_base: Op,
+    cells: HashMap<usize, Expr>,
+    constraints: Vec<Constraint>,
+    outputs: Vec<(usize, Expr)>,
+    inputs: usize,
+}
+
+impl Symbolic {
+    /// Makes the cell at `address` a variable, `Var::Cell(address)`
+    pub fn symbol(mut self, address: usize) -> Symbolic {
+        self.symbols.push(address);
+        self
+    }
+
+    /// Ends paths at their first input instead of making it a variable
+    pub fn without_input(mut self) -> Symbolic {
+        self.input = false;
+        self
+    }
+
+    /// Instructions to execute across all paths, after which any still
+    /// going end with `End::OutOfSteps`
+    pub fn budget(mut self, steps: u64) -> Symbolic {
+        self.budget = steps;
+        self
+    }
+
+    /// The program run straight through, ending with `End::Symbolic` at
+    /// the first jump that would fork
+    pub fn run(&self) -> Path {
+        let mut steps = 0;
+        self.follow(self.start(), &mut steps, None)
+    }
+
+    /// Every path through the program, up to the budget
+    pub fn explore(&self) -> Vec<Path> {
+        let mut pending = vec![self.start()];
+        let mut paths = Vec::new();
+        let mut steps = 0;
+        while let Some(state) = pending.pop() {
+            paths.push(self.follow(state, &mut steps, Some(&mut pending)));
+        }
+
+        paths
+    }
+
+    /// Paths that run the output instruction at `ip`. Their constraints
+    /// say which inputs get there.
+    pub fn reaching(&self, ip: usize) -> Vec<Path> {
+        let paths = self.explore().into_iter();
+        paths.filter(|path| path.reaches(ip)).collect()
+    }
+
+    fn start(&self) -> State {
+        let mut start = State::default();
+        for &address in self.symbols.iter() {
+            start.cells.insert(address, Expr::Var(Var::Cell(address)));
+        }
+        start
+    }
+
+    /// Runs `state` until its path ends, pushing the other side of any
+    /// fork onto `forks`. Without anywhere to put them, forks end the path.
+    fn follow(
+        &self,
+        mut state: State,
+        steps: &mut u64,
+        mut forks: Option<&mut Vec<State>>,
+    ) -> Path {
+        let end = loop {
+            if *steps == self.budget {
+                break End::OutOfSteps;
+            }
+            *steps += 1;
+
+            match self.step(&mut state, forks.is_some()) {
+                Ok(Some(fork)) => {
+                    if let Some(forks) = forks.as_mut() {
+                        forks.push(fork);
+                    }
+                }
+                Ok(None) => {}
+                Err(end) => break end,
+            }
+        };
+
+        Path {
+            constraints: state.constraints,
+            outputs: state.outputs,
+            inputs: state.inputs,
+            end,
+            symbols: self.symbols.clone(),
+            memory: self.memory.clone(),
+            cells: state.cells,
+        }
+    }
+
+    /// Executes one instruction, returning the other side of a fork if
+    /// it branched, or how the path ended
+    fn step(&self, state: &mut State, fork: bool) -> Result<Option<State>, End> {
+        let ip = state.ip;
+        let opcode = match self.cell(state, ip) {
+            Expr::Const(opcode) => opcode,
+            _ => return Err(End::Symbolic { ip }),
+        };
+        // Symbolic operands decode as 0, they're read again below
+        let instruction = Instruction::decode(ip, |address| match self.cell(state, address) {
+            Expr::Const(value) => value,
+            _ => 0,
+        })
+        .map_err(End::Fault)?;
+
+        let params = instruction.params();
+        let operands: Vec<Expr> = (1..instruction.size())
+            .map(|n| self.cell(state, ip + n))
+            .collect();
+        let operand = |n: usize| operands[n].clone();
+        let at = Executing {
+            memory: &self.memory,
+            ip,
+            opcode,
+        };
+        let mut next = ip + instruction.size();
+        match instruction {
+            Instruction::Add(..) | Instruction::Multiply(..) => {
+                let a = at.read(state, params[0], operand(0))?;
+                let b = at.read(state, params[1], operand(1))?;
+                let value = match instruction {
+                    Instruction::Add(..) => at.arithmetic(a, b, Expr::sum, Op::checked_add)?,
+                    _ => at.arithmetic(a, b, Expr::product, Op::checked_mul)?,
+                };
+                let address = at.write_address(state, params[2], operand(2))?;
+                state.cells.insert(address, value);
+            }
+            Instruction::LessThan(..) | Instruction::Equals(..) => {
+                let a = at.read(state, params[0], operand(0))?;
+                let b = at.read(state, params[1], operand(1))?;
+                let value = match instruction {
+                    Instruction::LessThan(..) => Expr::less_than(a, b),
+                    _ => Expr::equals(a, b),
+                };
+                let address = at.write_address(state, params[2], operand(2))?;
+                state.cells.insert(address, value);
+            }
+            Instruction::Input(param) => {
+                if !self.input {
+                    return Err(End::NeedsInput);
+                }
+                let address = at.write_address(state, param, operand(0))?;
+                state
+                    .cells
+                    .insert(address, Expr::Var(Var::Input(state.inputs)));
+                state.inputs += 1;
+            }
+            Instruction::Output(param) => {
+                let value = at.read(state, param, operand(0))?;
+                state.outputs.push((ip, value));
+            }
+            Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) => {
+                let if_true = matches!(instruction, Instruction::JumpIfTrue(..));
+                let condition = at.read(state, params[0], operand(0))?;
+                let known = match &condition {
+                    Expr::Const(value) => Some(*value != 0),
+                    condition if condition.has_unknown() => return Err(End::Symbolic { ip }),
+                    // A condition already decided on this path goes the same way
+                    condition => (state.constraints.iter())
+                        .find(|constraint| constraint.condition == *condition)
+                        .map(|constraint| constraint.holds),
+                };
+
+                match known {
+                    Some(value) if value == if_true => {
+                        next = at.target(state, params[1], operand(1))?;
+                    }
+                    Some(_) => {}
+                    None if !fork => return Err(End::Symbolic { ip }),
+                    None => {
+                        let target = at.target(state, params[1], operand(1))?;
+                        let mut jumped = state.clone();
+                        jumped.constraints.push(Constraint {
+                            condition: condition.clone(),
+                            holds: if_true,
+                        });
+                        jumped.ip = target;
+                        state.constraints.push(Constraint {
+                            condition,
+                            holds: !if_true,
+                        });
+                        state.ip = next;
+                        return Ok(Some(jumped));
+                    }
+                }
+            }
+            Instruction::AdjustRelativeBase(param) => {
+                let offset = match at.read(state, param, operand(0))? {
+                    Expr::Const(offset) => offset,
+                    _ => return Err(End::Symbolic { ip }),
+                };
+                state.relative_base = match state.relative_base.checked_add(offset) {
+                    Some(base) => base,
+                    None => return Err(at.overflow(state.relative_base, offset)),
+                };
+            }
+            Instruction::Halt => return Err(End::Halted),
+            Instruction::Data(_) => unreachable!("decode never yields data"),
+        }
+
+        state.ip = next;
+        Ok(None)
+    }
+
+    fn cell(&self, state: &State, address: usize) -> Expr {
+        read(&self.memory, &state.cells, address)
+    }
+}
+
+fn read(memory: &Memory, cells: &HashMap<usize, Expr>, address: usize) -> Expr {
+    match cells.get(&address) {
+        Some(value) => value.clone(),
+        None => Expr::Const(memory.get(address)),
+    }
+}
+
+/// The instruction being executed, for reading its operands and
+/// describing its faults
+struct Executing<'a> {
+    memory: &'a Memory,
+    ip: usize,
+    opcode: Op,
+}
+
+impl Executing<'_> {
+    fn overflow(&self, a: Op, b: Op) -> End {
+        End::Fault(VmError::Overflow {
+            ip: self.ip,
+            opcode: self.opcode,
+            a,
+            b,
+        })
+    }
+
+    fn negative(&self, operand: Op, address: Op) -> End {
+        End::Fault(VmError::NegativeAddress {
+            ip: self.ip,
+            opcode: self.opcode,
+            operand,
+            address,
+        })
+    }
+
+    fn arithmetic(
+        &self,
+        a: Expr,
+        b: Expr,
+        build: fn(Expr, Expr) -> Expr,
+        checked: fn(Op, Op) -> Option<Op>,
+    ) -> Result<Expr, End> {
+        match (&a, &b) {
+            (&Expr::Const(x), &Expr::Const(y)) if checked(x, y).is_none() => {
+                Err(self.overflow(x, y))
+            }
+            _ => Ok(build(a, b)),
+        }
+    }
+
+    /// Where a parameter points, which may be an expression
+    fn address(&self, state: &State, param: Param, operand: Expr) -> Result<Expr, End> {
+        match param {
+            Param::Position(_) => Ok(operand),
+            Param::Relative(_) => {
+                let base = Expr::Const(state.relative_base);
+                self.arithmetic(operand, base, Expr::sum, Op::checked_add)
+            }
+            Param::Immediate(operand) => Err(End::Fault(VmError::ImmediateWrite {
+                ip: self.ip,
+                opcode: self.opcode,
+                operand,
+            })),
+        }
+    }
+
+    fn read(&self, state: &mut State, param: Param, operand: Expr) -> Result<Expr, End> {
+        if let Param::Immediate(_) = param {
+            return Ok(operand);
+        }
+
+        match self.address(state, param, operand)? {
+            Expr::Const(address) if address < 0 => Err(self.negative(param.operand(), address)),
+            Expr::Const(address) => Ok(read(self.memory, &state.cells, address as usize)),
+            address if address.has_unknown() => Err(End::Symbolic { ip: self.ip }),
+            address => {
+                state.constraints.push(Constraint {
+                    condition: Expr::less_than(address, Expr::Const(0)),
+                    holds: false,
+                });
+                Ok(Expr::Unknown)
+            }
+        }
+    }
+
+    fn write_address(&self, state: &State, param: Param, operand: Expr) -> Result<usize, End> {
+        match self.address(state, param, operand)? {
+            Expr::Const(address) if address < 0 => Err(self.negative(param.operand(), address)),
+            Expr::Const(address) => Ok(address as usize),
+            _ => Err(End::Symbolic { ip: self.ip }),
+        }
+    }
+
+    fn target(&self, state: &mut State, param: Param, operand: Expr) -> Result<usize, End> {
+        match self.read(state, param, operand)? {
+            Expr::Const(target) if target < 0 => Err(self.negative(target, target)),
+            Expr::Const(target) => Ok(target as usize),
+            _ => Err(End::Symbolic { ip: self.ip }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntCode, Status};
+
+    // Outputs 999 below 8, 1000 for 8 and 1001 above
+    const AROUND_8: &str = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
+
+    fn explore(program: &str) -> Vec<Path> {
+        Program::new(program).unwrap().symbolic().explore()
+    }
+
+    #[test]
+    fn comparisons() {
+        for &(program, expected) in [
+            ("3,9,8,9,10,9,4,9,99,-1,8", "in0 == 8"),
+            ("3,3,1107,-1,8,3,4,3,99", "in0 < 8"),
+        ]
+        .iter()
+        {
+            let paths = explore(program);
+            assert_eq!(paths.len(), 1);
+            assert_eq!(paths[0].end, End::Halted);
+            assert!(paths[0].constraints.is_empty());
+            assert_eq!(paths[0].outputs.len(), 1);
+            assert_eq!(paths[0].outputs[0].1.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn forks_on_input() {
+        let paths = explore("3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9");
+        let constraints: Vec<String> = (paths.iter())
+            .map(|path| path.constraints[0].to_string())
+            .collect();
+        assert_eq!(constraints, vec!["in0 != 0", "in0 == 0"]);
+    }
+
+    #[test]
+    fn examples_take_their_path() {
+        let paths = explore(AROUND_8);
+        assert_eq!(paths.len(), 3);
+        for path in paths {
+            let example = path.example().unwrap();
+            let mut computer = IntCode::new(AROUND_8).unwrap();
+            computer.input(example[&Var::Input(0)]);
+            assert_eq!(computer.run(), Ok(Status::Halted));
+
+            let outputs: Vec<Op> = (path.outputs.iter())
+                .map(|(_, value)| value.eval(&example).unwrap())
+                .collect();
+            assert_eq!(computer.output(), &outputs);
+        }
+    }
+
+    #[test]
+    fn run_stops_at_forks() {
+        let path = Program::new(AROUND_8).unwrap().symbolic().run();
+        assert_eq!(path.end, End::Symbolic { ip: 6 });
+        assert!(path.constraints.is_empty());
+    }
+
+    #[test]
+    fn decided_conditions_dont_fork() {
+        // Jumps on the input twice, the second jump can only go one way
+        let paths = explore("3,20,1005,20,8,104,0,99,1005,20,14,104,1,99,104,2,99");
+        let outputs: Vec<Expr> = (paths.iter())
+            .map(|path| path.outputs[0].1.clone())
+            .collect();
+        assert_eq!(outputs, vec![Expr::Const(0), Expr::Const(2)]);
+    }
+
+    #[test]
+    fn patched_cells() {
+        // Shaped like day 2, reading through both cells before overwriting
+        // what it read
+        let program = Program::new("1,0,0,3,1,1,2,3,2,3,17,0,1,0,1,0,99,5").unwrap();
+        let path = program.symbolic().symbol(1).symbol(2).run();
+        assert_eq!(path.end, End::Halted);
+        assert_eq!(
+            (path.constraints.iter())
+                .map(Constraint::to_string)
+                .collect::<Vec<_>>(),
+            vec!["[1] >= 0", "[2] >= 0"]
+        );
+        assert_eq!(path.cell(0).to_string(), "(([1] + [2]) * 5) + [1]");
+        assert_eq!(
+            path.cell(0).linear().unwrap().to_string(),
+            "0 + 6*[1] + 5*[2]"
+        );
+    }
+
+    #[test]
+    fn outputs() {
+        let path = Program::new("1001,7,1,7,4,7,99,0")
+            .unwrap()
+            .symbolic()
+            .symbol(7)
+            .run();
+        assert!(path.reaches(4));
+        assert_eq!(path.outputs[0].1.to_string(), "[7] + 1");
+    }
+
+    #[test]
+    fn needs_concrete_values() {
+        let run = |program: &str, symbol: usize| {
+            let symbolic = Program::new(program).unwrap().symbolic();
+            symbolic.symbol(symbol).run().end
+        };
+        // A jump on the cell and a write through it
+        assert_eq!(run("1005,5,4,99,99,0", 5), End::Symbolic { ip: 0 });
+        assert_eq!(run("1101,1,1,5,99,0", 3), End::Symbolic { ip: 0 });
+    }
+
+    #[test]
+    fn symbolic_write_address() {
+        let paths = explore("3,5,1101,1,1,0,99");
+        assert_eq!(paths[0].end, End::Symbolic { ip: 2 });
+        assert_eq!(
+            Program::new("3,0,99")
+                .unwrap()
+                .symbolic()
+                .without_input()
+                .explore()[0]
+                .end,
+            End::NeedsInput
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! Machines as async tasks. An `AsyncMachine` is a stream of a machine's
+//! output that waits on a `Receiver` whenever the program wants input, so
+//! machines wired together with channels can run as ordinary futures:
+//!
+//! ```
+//! use intcode::{block_on, channel, join_all, IntCode};
+//!
+//! // Two machines that each add one to what they're given
+//! let (to_first, first_input) = channel();
+//! let (to_second, second_input) = channel();
+//! let (to_caller, mut results) = channel();
+//! let first = IntCode::new("3,9,101,1,9,9,4,9,99,0").unwrap();
+//! let second = first.clone();
+//!
+//! to_first.send(1);
+//! let machines = vec![
+//!     first.into_async(first_input).pipe(to_second),
+//!     second.into_async(second_input).pipe(to_caller),
+//! ];
+//! block_on(join_all(machines));
+//! assert_eq!(results.try_recv(), Some(3));
+//! ```
+//!
+//! Nothing here needs a runtime. `block_on` and `join_all` are enough to
+//! drive a handful of machines, and the futures work with any executor.
+
+use crate::{Exit, IntCode, Op, Result, Status};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// Most steps a machine runs in one poll before yielding to other tasks
+const POLL_STEPS: u64 = 10_000;
+
+struct Shared {
+    queue: VecDeque<Op>,
+    senders: usize,
+    /// The receiver, if it's waiting on an empty queue
+    waker: Option<Waker>,
+}
+
+/// An unbounded channel of values whose receiving end can be awaited
+pub fn channel() -> (Sender, Receiver) {
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: VecDeque::new(),
+        senders: 1,
+        waker: None,
+    }));
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Sender {
+    /// Never blocks. Values sent after the receiver is dropped are lost.
+    pub fn send(&self, value: Op) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.queue.push_back(value);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Sender {
+        self.shared.lock().unwrap().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// Once the last sender is gone the receiver sees the end of the channel
+impl Drop for Sender {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.senders -= 1;
+        if shared.senders == 0 {
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+pub struct Receiver {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Receiver {
+    /// The next value, or `None` once the queue is empty and every sender
+    /// has been dropped
+    pub fn recv(&mut self) -> Recv<'_> {
+        Recv { receiver: self }
+    }
+
+    /// The next value if one is queued, without waiting
+    pub fn try_recv(&mut self) -> Option<Op> {
+        self.shared.lock().unwrap().queue.pop_front()
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<Op>> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.queue.pop_front() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if shared.senders == 0 => Poll::Ready(None),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Future returned by `Receiver::recv`
+pub struct Recv<'a> {
+    receiver: &'a mut Receiver,
+}
+
+impl Future for Recv<'_> {
+    type Output = Option<Op>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Op>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// A machine that takes its input from a channel, run a step at a time as
+/// a stream of its output. See `IntCode::into_async`.
+pub struct AsyncMachine {
+    computer: IntCode,
+    input: Receiver,
+    exit: Option<Exit>,
+}
+
+impl IntCode {
+    /// Turns the machine into an async stream of its output, awaiting
+    /// `input` whenever it has nothing queued to read
+    pub fn into_async(self, input: Receiver) -> AsyncMachine {
+        AsyncMachine {
+            computer: self,
+            input,
+            exit: None,
+        }
+    }
+}
+
+impl AsyncMachine {
+    /// Polls for the next output, shaped like `futures::Stream::poll_next`.
+    /// Runs until there's output, the program waits on an empty channel or
+    /// it has run a slice of steps, when it wakes itself and yields so
+    /// other tasks get a turn. `None` means the stream is over, `exit` says
+    /// why.
+    pub fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<Result<Op>>> {
+        while self.exit.is_none() {
+            match self.computer.resume_at_most(POLL_STEPS) {
+                Ok(Status::Output(value)) => return Poll::Ready(Some(Ok(value))),
+                Ok(Status::NeedsInput) => match self.input.poll_recv(cx) {
+                    Poll::Ready(Some(value)) => self.computer.input(value),
+                    Poll::Ready(None) => self.exit = Some(Exit::Disconnected),
+                    Poll::Pending => return Poll::Pending,
+                },
+                Ok(Status::Halted) => self.exit = Some(Exit::Halted),
+                Ok(Status::BudgetExhausted) if self.computer.budget() != Some(0) => {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Ok(Status::BudgetExhausted) => self.exit = Some(Exit::OutOfBudget),
+                Err(error) => {
+                    // A fault ends the stream, the machine is left as it was
+                    self.exit = Some(Exit::Faulted);
+                    return Poll::Ready(Some(Err(error)));
+                }
+            }
+        }
+
+        Poll::Ready(None)
+    }
+
+    /// The next output, see `poll_next`
+    pub fn next_output(&mut self) -> Next<'_> {
+        Next { machine: self }
+    }
+
+    /// Sends every output to `output` until the stream ends, resolving to
+    /// the machine, or to the fault if it had one. Yields after every
+    /// output, so machines that never wait on input still take turns.
+    pub fn pipe(self, output: Sender) -> Pipe {
+        Pipe {
+            machine: Some(self),
+            output: Some(output),
+        }
+    }
+
+    /// Why the stream ended, `None` while it's still going. A machine
+    /// stuck waiting on input stays pending rather than deadlocking.
+    pub fn exit(&self) -> Option<Exit> {
+        self.exit
+    }
+
+    pub fn computer(&self) -> &IntCode {
+        &self.computer
+    }
+
+    /// The machine and its input channel, with anything still queued on it
+    pub fn into_inner(self) -> (IntCode, Receiver) {
+        (self.computer, self.input)
+    }
+}
+
+/// Future returned by `AsyncMachine::next_output`
+pub struct Next<'a> {
+    machine: &'a mut AsyncMachine,
+}
+
+impl Future for Next<'_> {
+    type Output = Option<Result<Op>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Op>>> {
+        self.machine.poll_next(cx)
+    }
+}
+
+/// Future returned by `AsyncMachine::pipe`. Drops its sender when done, so
+/// whoever reads the other end sees the channel close.
+pub struct Pipe {
+    machine: Option<AsyncMachine>,
+    output: Option<Sender>,
+}
+
+impl Future for Pipe {
+    type Output = Result<AsyncMachine>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<AsyncMachine>> {
+        let this = &mut *self;
+        let machine = this.machine.as_mut().expect("pipe polled after finishing");
+        let result = match machine.poll_next(cx) {
+            Poll::Ready(Some(Ok(value))) => {
+                if let Some(output) = &this.output {
+                    output.send(value);
+                }
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(Some(Err(error))) => Err(error),
+            Poll::Ready(None) => Ok(this.machine.take().unwrap()),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        this.output = None;
+        Poll::Ready(result)
+    }
+}
+
+/// Runs every future to completion, resolving to their outputs in order
+pub fn join_all<F: Future>(futures: Vec<F>) -> JoinAll<F> {
+    JoinAll {
+        outputs: futures.iter().map(|_| None).collect(),
+        futures: futures
+            .into_iter()
+            .map(|future| Some(Box::pin(future)))
+            .collect(),
+    }
+}
+
+/// Future returned by `join_all`
+pub struct JoinAll<F: Future> {
+    futures: Vec<Option<Pin<Box<F>>>>,
+    outputs: Vec<Option<F::Output>>,
+}
+
+// The futures are pinned in their own boxes and outputs are never pinned
+impl<F: Future> Unpin for JoinAll<F> {}
+
+impl<F: Future> Future for JoinAll<F> {
+    type Output = Vec<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Vec<F::Output>> {
+        // Every future shares the one waker, so whichever woke us up, poll
+        // all that are still going
+        let this = &mut *self;
+        for (slot, output) in this.futures.iter_mut().zip(this.outputs.iter_mut()) {
+            if let Some(future) = slot {
+                if let Poll::Ready(value) = future.as_mut().poll(cx) {
+                    *output = Some(value);
+                    *slot = None;
+                }
+            }
+        }
+
+        if this.futures.iter().any(Option::is_some) {
+            return Poll::Pending;
+        }
+
+        Poll::Ready(
+            this.outputs
+                .iter_mut()
+                .map(|output| output.take().unwrap())
+                .collect(),
+        )
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future on the current thread, parking it while nothing is ready.
+/// Machines waiting on each other in a cycle with nothing in flight never
+/// wake up again, so this never returns for them.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VmError;
+
+    // Outputs double each input until it reads a zero
+    const DOUBLER: &str = "3,15,1006,15,14,1002,15,2,15,4,15,1105,1,0,99,0";
+
+    #[test]
+    fn stream_awaits_input() {
+        let (input, receiver) = channel();
+        let mut machine = IntCode::new(DOUBLER).unwrap().into_async(receiver);
+        let outputs = block_on(async {
+            let mut outputs = Vec::new();
+            for &value in [3, 5, 0].iter() {
+                input.send(value);
+                if let Some(output) = machine.next_output().await {
+                    outputs.push(output.unwrap());
+                }
+            }
+            outputs
+        });
+
+        assert_eq!(outputs, vec![6, 10]);
+        assert_eq!(machine.exit(), Some(Exit::Halted));
+    }
+
+    #[test]
+    fn closed_input() {
+        let (input, receiver) = channel();
+        input.send(4);
+        drop(input);
+
+        let (output, mut results) = channel();
+        let machine = block_on(
+            IntCode::new(DOUBLER)
+                .unwrap()
+                .into_async(receiver)
+                .pipe(output),
+        );
+        let machine = machine.unwrap();
+        assert_eq!(machine.exit(), Some(Exit::Disconnected));
+        assert_eq!(results.try_recv(), Some(8));
+        assert_eq!(block_on(results.recv()), None);
+    }
+
+    #[test]
+    fn fault_ends_stream() {
+        let (_input, receiver) = channel();
+        let mut machine = IntCode::new("104,7,42").unwrap().into_async(receiver);
+        assert_eq!(block_on(machine.next_output()), Some(Ok(7)));
+        assert_eq!(
+            block_on(machine.next_output()),
+            Some(Err(VmError::UnknownOpcode {
+                ip: 2,
+                opcode: 42,
+                operand: Some(0)
+            }))
+        );
+        assert_eq!(block_on(machine.next_output()), None);
+        assert_eq!(machine.exit(), Some(Exit::Faulted));
+    }
+
+    #[test]
+    fn pipes_take_turns() {
+        // Sends 1 forever, and adds up three inputs
+        let (to_sum, sum_input) = channel();
+        let (to_caller, mut results) = channel();
+        let mut ones = IntCode::new("104,1,1105,1,0").unwrap();
+        ones.set_budget(Some(1000));
+        let sum = IntCode::new("3,20,3,21,3,22,1,20,21,20,1,20,22,20,4,20,99").unwrap();
+
+        let (_input, ones_input) = channel();
+        let mut ones = Box::pin(ones.into_async(ones_input).pipe(to_sum));
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        assert!(ones.as_mut().poll(&mut cx).is_pending());
+
+        let sum = sum.into_async(sum_input).pipe(to_caller);
+        let mut done = block_on(join_all(vec![ones, Box::pin(sum)]));
+        assert_eq!(results.try_recv(), Some(3));
+        let sum = done.pop().unwrap().unwrap();
+        let ones = done.pop().unwrap().unwrap();
+        assert_eq!(sum.exit(), Some(Exit::Halted));
+        assert_eq!(ones.exit(), Some(Exit::OutOfBudget));
+        assert_eq!(ones.computer().stats().steps, 1000);
+    }
+
+    #[test]
+    fn spinning_yields() {
+        let (_input, receiver) = channel();
+        let mut machine = IntCode::new("1105,1,0").unwrap().into_async(receiver);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        assert!(machine.poll_next(&mut cx).is_pending());
+        assert_eq!(machine.computer().stats().steps, POLL_STEPS);
+        assert_eq!(machine.exit(), None);
+    }
+
+    #[test]
+    fn woken_from_another_thread() {
+        let (input, receiver) = channel();
+        let (output, mut results) = channel();
+        let sender = thread::spawn(move || {
+            for &value in [1, 2, 0].iter() {
+                thread::sleep(std::time::Duration::from_millis(5));
+                input.send(value);
+            }
+        });
+
+        let pipe = IntCode::new(DOUBLER)
+            .unwrap()
+            .into_async(receiver)
+            .pipe(output);
+        assert!(block_on(pipe).is_ok());
+        sender.join().unwrap();
+        assert_eq!((results.try_recv(), results.try_recv()), (Some(2), Some(4)));
+    }
+}
@@ -0,0 +1,659 @@
+use crate::{
+    Instruction, IntCode, MachineState, NetworkError, Op, OutputFn, State, Status, VmError,
+    PIPELINE_OUTPUT,
+};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Steps a network's machine runs between checks for the idle timeout
+const SLICE: u64 = 10_000;
+
+/// Longest the host sleeps between looking at a network's progress
+const POLL: Duration = Duration::from_millis(10);
+
+/// Why a machine's thread finished
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Exit {
+    Halted,
+    /// Waited on input along with every other machine still running in its
+    /// network, with nothing left to send any of them
+    Deadlocked,
+    /// Needed input but every sender for its channel had hung up
+    Disconnected,
+    /// Used up the budget it was given with `IntCode::set_budget`
+    OutOfBudget,
+    /// Stopped on an error. Only async machines end this way, a thread
+    /// that faults finishes with the error instead.
+    Faulted,
+    /// Still going when its network's idle timeout ran out, see
+    /// `ThreadedNetwork::idle_timeout`
+    TimedOut,
+}
+
+/// A machine after its thread is done, along with its input channel so
+/// anything sent to it that it never read can still be collected
+pub struct Finished {
+    pub name: String,
+    pub computer: IntCode,
+    pub input: Receiver<Op>,
+    pub exit: Exit,
+}
+
+impl Finished {
+    fn state(&self, channel: &str) -> MachineState {
+        MachineState {
+            name: self.name.clone(),
+            ip: self.computer.ip(),
+            state: match self.exit {
+                Exit::Halted | Exit::Faulted => State::Halted,
+                Exit::OutOfBudget => State::Ready,
+                Exit::Deadlocked | Exit::Disconnected => State::Blocked {
+                    channel: channel.to_string(),
+                },
+                Exit::TimedOut => match self.computer.decode() {
+                    Ok(Instruction::Input(_)) => State::Blocked {
+                        channel: channel.to_string(),
+                    },
+                    _ => State::Ready,
+                },
+            },
+        }
+    }
+}
+
+pub struct MachineThread {
+    channel: String,
+    handle: JoinHandle<Result<Finished, NetworkError>>,
+}
+
+impl MachineThread {
+    /// Waits for the machine to halt, fault, lose its input or run out of
+    /// budget
+    pub fn join(self) -> Result<Finished, NetworkError> {
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+/// Runs `computer` on its own thread, reading from `input` and sending to
+/// `output`. Waits as long as it takes for input, until every sender for
+/// `input` has hung up. Output goes only to `output`, it isn't also
+/// collected in the machine, and is dropped once the receiver hangs up.
+/// `channel` is only used to describe what the machine was blocked on.
+pub fn spawn(
+    name: &str,
+    mut computer: IntCode,
+    channel: &str,
+    input: Receiver<Op>,
+    output: Sender<Op>,
+) -> MachineThread {
+    computer.set_output_port(output);
+    start(name, computer, channel, input, None)
+}
+
+fn start(
+    name: &str,
+    mut computer: IntCode,
+    channel: &str,
+    input: Receiver<Op>,
+    member: Option<Member>,
+) -> MachineThread {
+    let thread_name = name.to_string();
+    let handle = thread::spawn(move || {
+        let exit = run(&mut computer, &input, member.as_ref());
+        if let Some(member) = &member {
+            member.finished();
+        }
+        let exit = exit.map_err(|error| NetworkError::Fault {
+            machine: thread_name.clone(),
+            error,
+        })?;
+
+        Ok(Finished {
+            name: thread_name,
+            computer,
+            input,
+            exit,
+        })
+    });
+
+    MachineThread {
+        channel: channel.to_string(),
+        handle,
+    }
+}
+
+/// Runs the machine until it stops for good. Its output port does the
+/// sending.
+fn run(
+    computer: &mut IntCode,
+    input: &Receiver<Op>,
+    member: Option<&Member>,
+) -> Result<Exit, VmError> {
+    loop {
+        let status = match member {
+            Some(member) => match resume_sliced(computer, member)? {
+                Some(status) => status,
+                None => return Ok(Exit::TimedOut),
+            },
+            None => computer.resume()?,
+        };
+
+        match status {
+            Status::Output(_) => {}
+            Status::NeedsInput => {
+                if let Some(member) = member {
+                    member.waiting();
+                }
+                match input.recv() {
+                    Ok(value) => {
+                        if let Some(member) = member {
+                            member.received();
+                        }
+                        computer.input(value);
+                    }
+                    Err(_) if member.is_some_and(Member::deadlocked) => {
+                        return Ok(Exit::Deadlocked)
+                    }
+                    Err(_) if member.is_some_and(Member::timed_out) => return Ok(Exit::TimedOut),
+                    Err(_) => return Ok(Exit::Disconnected),
+                }
+            }
+            Status::Halted => return Ok(Exit::Halted),
+            Status::BudgetExhausted => return Ok(Exit::OutOfBudget),
+        }
+    }
+}
+
+/// `resume`, a slice of steps at a time so a machine that never stops
+/// running can still be stopped by the network's idle timeout, in which
+/// case it's `None`. Any budget the machine has is kept to.
+fn resume_sliced(computer: &mut IntCode, member: &Member) -> Result<Option<Status>, VmError> {
+    loop {
+        match computer.resume_at_most(SLICE)? {
+            Status::BudgetExhausted if computer.budget() != Some(0) => {
+                if member.timed_out() {
+                    return Ok(None);
+                }
+            }
+            status => return Ok(Some(status)),
+        }
+    }
+}
+
+/// A machine's place in a `ThreadedNetwork`, which hears when it waits on
+/// input so it can tell a stuck network from a busy one
+struct Member {
+    activity: Arc<Mutex<Activity>>,
+    input: String,
+    output: String,
+}
+
+impl Member {
+    fn waiting(&self) {
+        self.activity.lock().unwrap().waiting(&self.input);
+    }
+
+    fn received(&self) {
+        self.activity.lock().unwrap().received(&self.input);
+    }
+
+    /// Whether the network hung up on the machine because it's stuck
+    fn deadlocked(&self) -> bool {
+        self.activity.lock().unwrap().deadlocked
+    }
+
+    /// Whether the network gave up on making progress
+    fn timed_out(&self) -> bool {
+        self.activity.lock().unwrap().timed_out
+    }
+
+    fn finished(&self) {
+        (self.activity.lock().unwrap()).finished(&self.input, &self.output);
+    }
+}
+
+/// What a threaded network's channels are up to, shared between the
+/// machines' threads so whichever one leaves the network stuck notices
+#[derive(Default)]
+struct Activity {
+    channels: HashMap<String, Channel>,
+    /// The host has stopped sending
+    joined: bool,
+    deadlocked: bool,
+    timed_out: bool,
+    /// When a machine last read input or finished, from when the host
+    /// joined
+    progress: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Channel {
+    /// Dropped to hang up on the reader once nothing can write any more
+    sender: Option<Sender<Op>>,
+    /// Running machines that write to the channel
+    writers: usize,
+    /// Values sent that haven't been read
+    queued: usize,
+    /// Whether a running machine reads the channel
+    read: bool,
+    /// Whether that machine is blocked waiting on it
+    waiting: bool,
+}
+
+impl Activity {
+    fn channel(&mut self, name: &str) -> &mut Channel {
+        self.channels.entry(name.to_string()).or_default()
+    }
+
+    fn send(&mut self, name: &str, value: Op) {
+        let channel = self.channel(name);
+        let sent = channel.sender.as_ref().map(|sender| sender.send(value));
+        if let Some(Ok(())) = sent {
+            channel.queued += 1;
+        }
+    }
+
+    fn waiting(&mut self, name: &str) {
+        self.channel(name).waiting = true;
+        self.check();
+    }
+
+    fn received(&mut self, name: &str) {
+        let channel = self.channel(name);
+        channel.waiting = false;
+        channel.queued -= 1;
+        self.progressed();
+    }
+
+    fn finished(&mut self, input: &str, output: &str) {
+        self.progressed();
+        let channel = self.channel(input);
+        channel.read = false;
+        channel.waiting = false;
+        self.channel(output).writers -= 1;
+        self.hang_up();
+        self.check();
+    }
+
+    fn join(&mut self) {
+        self.joined = true;
+        self.progress = Some(Instant::now());
+        self.hang_up();
+        self.check();
+    }
+
+    fn progressed(&mut self) {
+        if self.joined {
+            self.progress = Some(Instant::now());
+        }
+    }
+
+    /// Stops every machine still going, hanging up on those waiting and
+    /// telling the rest to stop at the end of their slice
+    fn time_out(&mut self) {
+        self.timed_out = true;
+        for channel in self.channels.values_mut() {
+            channel.sender = None;
+        }
+    }
+
+    /// Hangs up on channels nothing can write to any more, once the host
+    /// can't either
+    fn hang_up(&mut self) {
+        if self.joined {
+            for channel in self.channels.values_mut() {
+                if channel.writers == 0 {
+                    channel.sender = None;
+                }
+            }
+        }
+    }
+
+    /// Hangs up everything if every running machine is waiting on a
+    /// channel that's empty but still open, so nothing can ever arrive
+    fn check(&mut self) {
+        let mut reading = (self.channels.values())
+            .filter(|channel| channel.read)
+            .peekable();
+        let stuck = self.joined
+            && reading.peek().is_some()
+            && reading
+                .all(|channel| channel.waiting && channel.queued == 0 && channel.sender.is_some());
+
+        if stuck {
+            self.deadlocked = true;
+            for channel in self.channels.values_mut() {
+                channel.sender = None;
+            }
+        }
+    }
+}
+
+/// Like `Network`, but every machine gets its own thread and the channels
+/// are mpsc channels. Machines start as soon as they're added. Once the
+/// host joins the network, every machine still running waiting on input
+/// with nothing on the way is a deadlock. Machines that keep running
+/// without anything reading input, spinning or writing to a channel no
+/// one reads, are only caught with an `idle_timeout`.
+pub struct ThreadedNetwork {
+    activity: Arc<Mutex<Activity>>,
+    receivers: HashMap<String, Receiver<Op>>,
+    threads: Vec<MachineThread>,
+    idle_timeout: Option<Duration>,
+}
+
+impl ThreadedNetwork {
+    pub fn new() -> ThreadedNetwork {
+        ThreadedNetwork {
+            activity: Arc::new(Mutex::new(Activity::default())),
+            receivers: HashMap::new(),
+            threads: Vec::new(),
+            idle_timeout: None,
+        }
+    }
+
+    /// Once joined, stops every machine if none reads input or finishes
+    /// for `timeout`, and `join` fails with `NetworkError::TimedOut`
+    pub fn idle_timeout(mut self, timeout: Duration) -> ThreadedNetwork {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Each machine reads from a channel named after it and writes to the
+    /// next machine's, the last one wrapping around to the first
+    pub fn ring<N: Into<String>, I: IntoIterator<Item = (N, IntCode)>>(
+        machines: I,
+    ) -> ThreadedNetwork {
+        let machines: Vec<(String, IntCode)> = machines
+            .into_iter()
+            .map(|(name, computer)| (name.into(), computer))
+            .collect();
+        let first = machines.first().map(|(name, _)| name.clone());
+        ThreadedNetwork::chain(machines, first.as_deref().unwrap_or(PIPELINE_OUTPUT))
+    }
+
+    /// Like a ring, but the last machine writes to `PIPELINE_OUTPUT`
+    pub fn pipeline<N: Into<String>, I: IntoIterator<Item = (N, IntCode)>>(
+        machines: I,
+    ) -> ThreadedNetwork {
+        let machines: Vec<(String, IntCode)> = machines
+            .into_iter()
+            .map(|(name, computer)| (name.into(), computer))
+            .collect();
+        ThreadedNetwork::chain(machines, PIPELINE_OUTPUT)
+    }
+
+    fn chain(machines: Vec<(String, IntCode)>, last: &str) -> ThreadedNetwork {
+        let mut network = ThreadedNetwork::new();
+        let mut outputs: Vec<String> = machines.iter().skip(1).map(|(n, _)| n.clone()).collect();
+        outputs.push(last.to_string());
+
+        // Create every input channel up front, so a ring's last machine
+        // writes into the channel its first machine reads
+        for (name, _) in machines.iter() {
+            network.channel(name);
+        }
+
+        for ((name, computer), output) in machines.into_iter().zip(outputs) {
+            network.spawn(&name, computer, &name, &output);
+        }
+
+        network
+    }
+
+    /// Starts a machine reading from the `input` channel and writing to
+    /// `output`. Its output only goes to the channel, it isn't also
+    /// collected in the machine. Only one machine can read a given channel.
+    ///
+    /// # Panics
+    ///
+    /// If another machine already reads from `input`.
+    pub fn spawn(&mut self, name: &str, mut computer: IntCode, input: &str, output: &str) {
+        self.channel(input);
+        self.channel(output);
+        let receiver = self
+            .receivers
+            .remove(input)
+            .unwrap_or_else(|| panic!("channel {} already has a reader", input));
+
+        {
+            let mut activity = self.activity.lock().unwrap();
+            activity.channel(input).read = true;
+            activity.channel(output).writers += 1;
+        }
+
+        // Sends are counted, so the network knows what's still on the way
+        let activity = self.activity.clone();
+        let channel = output.to_string();
+        computer.set_output_port(OutputFn(move |value| {
+            activity.lock().unwrap().send(&channel, value)
+        }));
+
+        let member = Member {
+            activity: self.activity.clone(),
+            input: input.to_string(),
+            output: output.to_string(),
+        };
+        let thread = start(name, computer, input, receiver, Some(member));
+        self.threads.push(thread);
+    }
+
+    pub fn send(&mut self, channel: &str, value: Op) {
+        self.channel(channel);
+        self.activity.lock().unwrap().send(channel, value);
+    }
+
+    /// Takes the reading end of a channel no machine reads from, such as
+    /// `PIPELINE_OUTPUT`
+    pub fn take_receiver(&mut self, channel: &str) -> Option<Receiver<Op>> {
+        self.receivers.remove(channel)
+    }
+
+    /// Waits for every machine. Fails with the first fault in the order the
+    /// machines were added, otherwise with a deadlock if the machines ended
+    /// up all waiting on each other, or with a timeout if they stopped
+    /// making progress for longer than the idle timeout.
+    pub fn join(self) -> Result<Vec<Finished>, NetworkError> {
+        // Machines blocked on channels only the host writes to get hung up
+        // on, and if everything's already waiting that's a deadlock
+        self.activity.lock().unwrap().join();
+        if let Some(timeout) = self.idle_timeout {
+            self.watch(timeout);
+        }
+
+        let mut channels = Vec::new();
+        let mut results = Vec::new();
+        for thread in self.threads {
+            channels.push(thread.channel.clone());
+            results.push(thread.join());
+        }
+
+        let finished = results.into_iter().collect::<Result<Vec<Finished>, _>>()?;
+        let states = || {
+            finished
+                .iter()
+                .zip(channels.iter())
+                .map(|(machine, channel)| machine.state(channel))
+                .collect()
+        };
+        let exited = |exit| finished.iter().any(|machine| machine.exit == exit);
+        if exited(Exit::Deadlocked) {
+            return Err(NetworkError::Deadlock(states()));
+        }
+        if exited(Exit::TimedOut) {
+            return Err(NetworkError::TimedOut(states()));
+        }
+
+        Ok(finished)
+    }
+
+    /// Waits until every machine has finished, or times them all out once
+    /// none has made progress for `timeout`
+    fn watch(&self, timeout: Duration) {
+        loop {
+            if self
+                .threads
+                .iter()
+                .all(|thread| thread.handle.is_finished())
+            {
+                return;
+            }
+
+            let mut activity = self.activity.lock().unwrap();
+            let idle = activity.progress.map_or(timeout, |at| at.elapsed());
+            if idle >= timeout {
+                activity.time_out();
+                return;
+            }
+
+            drop(activity);
+            thread::sleep(POLL.min(timeout - idle));
+        }
+    }
+
+    /// Creates the channel if it's new
+    fn channel(&mut self, name: &str) {
+        let mut activity = self.activity.lock().unwrap();
+        if !activity.channels.contains_key(name) {
+            let (sender, receiver) = mpsc::channel();
+            activity.channel(name).sender = Some(sender);
+            self.receivers.insert(name.to_string(), receiver);
+        }
+    }
+}
+
+impl Default for ThreadedNetwork {
+    fn default() -> ThreadedNetwork {
+        ThreadedNetwork::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Day 7 part 2 example, feedback loop of five amplifiers
+    const AMPLIFIER: &str =
+        "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
+
+    // Doubles every input
+    const DOUBLER: &str = "3,9,1002,9,2,9,4,9,1105,0,0";
+
+    fn machines(program: &str, names: &[&'static str]) -> Vec<(&'static str, IntCode)> {
+        names
+            .iter()
+            .map(|&name| (name, IntCode::new(program).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn ring() {
+        let names = ["A", "B", "C", "D", "E"];
+        let mut network = ThreadedNetwork::ring(machines(AMPLIFIER, &names));
+        for (name, &phase) in names.iter().zip([9, 8, 7, 6, 5].iter()) {
+            network.send(name, phase);
+        }
+        network.send("A", 0);
+
+        let finished = network.join().unwrap();
+        assert!(finished.iter().all(|machine| machine.exit == Exit::Halted));
+        assert!(finished
+            .iter()
+            .all(|machine| machine.computer.output().is_empty()));
+        assert_eq!(finished[0].input.try_recv(), Ok(139_629_729));
+    }
+
+    #[test]
+    fn pipeline_disconnects() {
+        let mut network = ThreadedNetwork::pipeline(machines(DOUBLER, &["x2", "x4"]));
+        let output = network.take_receiver(PIPELINE_OUTPUT).unwrap();
+        network.send("x2", 1);
+        network.send("x2", 5);
+
+        // Dropping the host's senders cascades down the pipeline
+        let finished = network.join().unwrap();
+        assert_eq!(finished[0].exit, Exit::Disconnected);
+        assert_eq!(finished[1].exit, Exit::Disconnected);
+        assert_eq!(output.iter().collect::<Vec<Op>>(), vec![4, 20]);
+    }
+
+    #[test]
+    fn waits_for_the_host() {
+        // Everything is waiting, but the host can still send
+        let mut network = ThreadedNetwork::pipeline(machines(DOUBLER, &["x2", "x4"]));
+        let output = network.take_receiver(PIPELINE_OUTPUT).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        network.send("x2", 3);
+
+        network.join().unwrap();
+        assert_eq!(output.iter().collect::<Vec<Op>>(), vec![12]);
+    }
+
+    #[test]
+    fn deadlock() {
+        // Both wait on each other forever once the third machine, which
+        // also writes to a, has halted
+        let mut network = ThreadedNetwork::ring(machines(DOUBLER, &["a", "b"]));
+        network.spawn("c", IntCode::new("99").unwrap(), "c", "a");
+        match network.join() {
+            Err(NetworkError::Deadlock(states)) => {
+                assert_eq!(states.len(), 3);
+                assert_eq!(states[0].to_string(), "a blocked on channel a at ip 0");
+                assert_eq!(states[2].to_string(), "c halted at ip 0");
+            }
+            _ => panic!("expected deadlock"),
+        }
+    }
+
+    #[test]
+    fn idle_timeout() {
+        // b waits on a, which spins forever without writing anything
+        let mut network = ThreadedNetwork::new().idle_timeout(Duration::from_millis(50));
+        network.spawn("a", IntCode::new("1105,1,0").unwrap(), "a", "b");
+        network.spawn("b", IntCode::new(DOUBLER).unwrap(), "b", "out");
+        match network.join() {
+            Err(NetworkError::TimedOut(states)) => {
+                assert_eq!(states[0].to_string(), "a ready at ip 0");
+                assert_eq!(states[1].to_string(), "b blocked on channel b at ip 0");
+            }
+            _ => panic!("expected timeout"),
+        }
+    }
+
+    #[test]
+    fn idle_timeout_keeps_budget() {
+        let mut computer = IntCode::new("1105,1,0").unwrap();
+        computer.set_budget(Some(SLICE * 2 + 5));
+        let mut network = ThreadedNetwork::new().idle_timeout(Duration::from_secs(10));
+        network.spawn("spin", computer, "spin", "out");
+        let finished = network.join().unwrap();
+        assert_eq!(finished[0].exit, Exit::OutOfBudget);
+        assert_eq!(finished[0].computer.stats().steps, SLICE * 2 + 5);
+    }
+
+    #[test]
+    fn fault() {
+        let (sender, input) = mpsc::channel();
+        let (output, _) = mpsc::channel();
+        drop(sender);
+        let thread = spawn("bad", IntCode::new("42").unwrap(), "in", input, output);
+        assert_eq!(
+            thread.join().err(),
+            Some(NetworkError::Fault {
+                machine: "bad".to_string(),
+                error: VmError::UnknownOpcode {
+                    ip: 0,
+                    opcode: 42,
+                    operand: Some(0)
+                },
+            })
+        );
+    }
+}
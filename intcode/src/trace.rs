@@ -0,0 +1,274 @@
+use crate::{Instruction, Op, Status, VmError};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// What a single instruction did, handed to tracers after it executes
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Step {
+    pub ip: usize,
+    pub instruction: Instruction,
+    values: [Op; 2],
+    value_count: usize,
+    /// Cell written and the value stored there
+    pub write: Option<(usize, Op)>,
+    /// New relative base, if the instruction changed it
+    pub relative_base: Option<Op>,
+    pub next_ip: usize,
+    /// Set when the instruction hands control back to the caller
+    pub status: Option<Status>,
+}
+
+impl Step {
+    pub(crate) fn new(ip: usize, instruction: Instruction) -> Step {
+        Step {
+            ip,
+            instruction,
+            values: [0; 2],
+            value_count: 0,
+            write: None,
+            relative_base: None,
+            next_ip: ip + instruction.size(),
+            status: None,
+        }
+    }
+
+    /// Records a resolved operand, passing it through
+    pub(crate) fn value(&mut self, value: Op) -> Op {
+        self.values[self.value_count] = value;
+        self.value_count += 1;
+        value
+    }
+
+    /// Resolved values of the operands that were read, in order
+    pub fn values(&self) -> &[Op] {
+        &self.values[..self.value_count]
+    }
+
+    pub fn jumped(&self) -> bool {
+        self.next_ip != self.ip + self.instruction.size() && self.status.is_none()
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instruction = self.instruction.to_string();
+        write!(f, "{:>6}: {:<32} ;", self.ip, instruction)?;
+        for value in self.values() {
+            write!(f, " {}", value)?;
+        }
+
+        if let Some((address, value)) = self.write {
+            write!(f, " [{}]={}", address, value)?;
+        }
+
+        if let Some(base) = self.relative_base {
+            write!(f, " r={}", base)?;
+        }
+
+        if self.jumped() {
+            write!(f, " ip={}", self.next_ip)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Hook called by `IntCode` around every instruction it executes.
+/// An input instruction that blocks for lack of input isn't executed, so
+/// it isn't traced either.
+pub trait Tracer {
+    fn before(&mut self, _ip: usize, _instruction: &Instruction) {}
+    fn after(&mut self, _step: &Step) {}
+    /// The instruction at `ip` faulted
+    fn fault(&mut self, _ip: usize, _error: &VmError) {}
+}
+
+/// Lets the caller keep a handle on a tracer after handing it to a machine
+impl<T: Tracer> Tracer for Arc<Mutex<T>> {
+    fn before(&mut self, ip: usize, instruction: &Instruction) {
+        self.lock().unwrap().before(ip, instruction);
+    }
+
+    fn after(&mut self, step: &Step) {
+        self.lock().unwrap().after(step);
+    }
+
+    fn fault(&mut self, ip: usize, error: &VmError) {
+        self.lock().unwrap().fault(ip, error);
+    }
+}
+
+/// Writes one line per executed instruction
+pub struct LogTracer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> LogTracer<W> {
+    pub fn new(out: W) -> LogTracer<W> {
+        LogTracer { out }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Tracer for LogTracer<W> {
+    // Tracing is best effort, a broken log shouldn't stop the machine
+    fn after(&mut self, step: &Step) {
+        let _ = writeln!(self.out, "{}", step);
+    }
+
+    fn fault(&mut self, ip: usize, error: &VmError) {
+        let _ = writeln!(self.out, "{:>6}: fault: {}", ip, error);
+    }
+}
+
+/// Keeps only the last `capacity` steps, plus the fault if there was one,
+/// for dumping after something goes wrong
+pub struct RingTracer {
+    steps: VecDeque<Step>,
+    capacity: usize,
+    fault: Option<(usize, VmError)>,
+}
+
+impl RingTracer {
+    pub fn new(capacity: usize) -> RingTracer {
+        RingTracer {
+            steps: VecDeque::with_capacity(capacity),
+            capacity,
+            fault: None,
+        }
+    }
+
+    /// Oldest first
+    pub fn steps(&self) -> impl Iterator<Item = &Step> {
+        self.steps.iter()
+    }
+
+    pub fn fault(&self) -> Option<&(usize, VmError)> {
+        self.fault.as_ref()
+    }
+
+    pub fn dump<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for step in self.steps.iter() {
+            writeln!(out, "{}", step)?;
+        }
+
+        if let Some((ip, error)) = &self.fault {
+            writeln!(out, "{:>6}: fault: {}", ip, error)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Tracer for RingTracer {
+    fn after(&mut self, step: &Step) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.steps.len() == self.capacity {
+            self.steps.pop_front();
+        }
+
+        self.steps.push_back(*step);
+    }
+
+    fn fault(&mut self, ip: usize, error: &VmError) {
+        self.fault = Some((ip, error.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntCode;
+
+    #[test]
+    fn log_lines() {
+        let log = Arc::new(Mutex::new(LogTracer::new(Vec::new())));
+        let mut computer = IntCode::new("109,5,21101,2,3,0,1005,5,10,0,204,0,99").unwrap();
+        computer.set_tracer(log.clone());
+        computer.run().unwrap();
+
+        let text = String::from_utf8(log.lock().unwrap().get_ref().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().map(|line| line.trim_end()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "     0: ARB #5                           ; 5 r=5",
+                "     2: ADD #2, #3 -> [r+0]              ; 2 3 [5]=5",
+                "     6: JT [5], #10                      ; 5 10 ip=10",
+                "    10: OUT [r+0]                        ; 5",
+                "    12: HLT                              ;",
+            ]
+        );
+    }
+
+    #[test]
+    fn ring_keeps_last_steps() {
+        let ring = Arc::new(Mutex::new(RingTracer::new(2)));
+        let mut computer = IntCode::new("1101,1,1,0,1101,2,2,0,1101,3,3,0,42").unwrap();
+        computer.set_tracer(ring.clone());
+        assert!(computer.run().is_err());
+
+        let ring = ring.lock().unwrap();
+        let ips: Vec<usize> = ring.steps().map(|step| step.ip).collect();
+        assert_eq!(ips, vec![4, 8]);
+        assert_eq!(
+            ring.fault(),
+            Some(&(
+                12,
+                VmError::UnknownOpcode {
+                    ip: 12,
+                    opcode: 42,
+                    operand: Some(0)
+                }
+            ))
+        );
+
+        let mut dump = Vec::new();
+        ring.dump(&mut dump).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+        assert_eq!(dump.lines().count(), 3);
+        assert!(dump.ends_with("    12: fault: unknown opcode 42 at ip 12 (operand 0)\n"));
+    }
+
+    #[test]
+    fn before_and_after_pair_up() {
+        #[derive(Default)]
+        struct Counter {
+            before: Vec<usize>,
+            after: Vec<usize>,
+        }
+
+        impl Tracer for Counter {
+            fn before(&mut self, ip: usize, _: &Instruction) {
+                self.before.push(ip);
+            }
+
+            fn after(&mut self, step: &Step) {
+                self.after.push(step.ip);
+            }
+        }
+
+        let counter = Arc::new(Mutex::new(Counter::default()));
+        let mut computer = IntCode::new("3,0,4,0,99").unwrap();
+        computer.set_tracer(counter.clone());
+        assert_eq!(computer.run(), Ok(Status::NeedsInput));
+        computer.input(7);
+        assert_eq!(computer.run(), Ok(Status::Halted));
+
+        let counter = counter.lock().unwrap();
+        assert_eq!(counter.before, vec![0, 2, 4]);
+        assert_eq!(counter.after, counter.before);
+    }
+}